@@ -13,6 +13,126 @@ CREATE INDEX labels_like_rkey ON labels (like_rkey)
 WHERE
     NOT neg;
 
+-- Latest state per (uri, val), maintained by labels::emit in the same
+-- transaction as the append-only log above. A negation updates the row
+-- (neg = true) rather than deleting it. `exp` and `cts` are the label's own.
+-- emit skips a label that would leave the row as it is (same neg, exp and
+-- like), and a like's label it gave before, so a replayed like adds nothing.
+-- Migration for existing deployments (the ingester rebuilds it from `labels`
+-- on startup while it is empty):
+--   CREATE TABLE current_labels (uri TEXT NOT NULL, val TEXT NOT NULL, neg BOOLEAN NOT NULL, exp TIMESTAMPTZ, like_rkey TEXT NOT NULL, cts TIMESTAMPTZ NOT NULL, seq BIGINT NOT NULL, PRIMARY KEY (uri, val));
+CREATE TABLE current_labels (
+    uri TEXT NOT NULL,
+    val TEXT NOT NULL,
+    neg BOOLEAN NOT NULL,
+    exp TIMESTAMPTZ,
+    like_rkey TEXT NOT NULL,
+    cts TIMESTAMPTZ NOT NULL,
+    seq BIGINT NOT NULL,
+    PRIMARY KEY (uri, val)
+);
+
+-- For negating everyone's labels for an event that left the feed.
+-- Migration for existing deployments:
+--   CREATE INDEX current_labels_val ON current_labels (val) WHERE NOT neg;
+CREATE INDEX current_labels_val ON current_labels (val)
+WHERE
+    NOT neg;
+
+-- Highest seq labels::compact has deleted from `labels`. subscribeLabels
+-- reports cursors below it as outdated, and never lets its head fall below
+-- it, so seqs stay monotonic after the newest rows are pruned.
+-- Migration for existing deployments:
+--   CREATE TABLE labels_compaction (horizon BIGINT NOT NULL);
+--   CREATE UNIQUE INDEX labels_compaction_single_row ON labels_compaction ((true));
+CREATE TABLE labels_compaction (horizon BIGINT NOT NULL);
+
+CREATE UNIQUE INDEX labels_compaction_single_row ON labels_compaction ((true));
+
+-- Accounts that blocked the labeler: none of their likes are labeled until
+-- the block (block_rkey) is deleted. See src/opt_out.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE opt_outs (did TEXT PRIMARY KEY, block_rkey TEXT NOT NULL, created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP);
+CREATE TABLE opt_outs (
+    did TEXT PRIMARY KEY,
+    block_rkey TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Labels negated because their account was taken down, suspended or
+-- deactivated, for re-applying on reactivation. See src/accounts.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE suspended_labels (uri TEXT NOT NULL, val TEXT NOT NULL, exp TIMESTAMPTZ, like_rkey TEXT NOT NULL, PRIMARY KEY (uri, val));
+CREATE TABLE suspended_labels (
+    uri TEXT NOT NULL,
+    val TEXT NOT NULL,
+    exp TIMESTAMPTZ,
+    like_rkey TEXT NOT NULL,
+    PRIMARY KEY (uri, val)
+);
+
+-- Last known handle per DID, from Jetstream identity events. See
+-- src/handles.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE handles (did TEXT PRIMARY KEY, handle TEXT, updated_at TIMESTAMPTZ NOT NULL);
+--   CREATE INDEX handles_handle ON handles (lower(handle));
+CREATE TABLE handles (
+    did TEXT PRIMARY KEY,
+    handle TEXT,
+    updated_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX handles_handle ON handles (lower(handle));
+
+-- Likes over the per-user label cap (max_labels_per_user), waiting for a
+-- slot. See src/label_cap.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE deferred_labels (uri TEXT NOT NULL, val TEXT NOT NULL, exp TIMESTAMPTZ, like_rkey TEXT NOT NULL, PRIMARY KEY (uri, val));
+CREATE TABLE deferred_labels (
+    uri TEXT NOT NULL,
+    val TEXT NOT NULL,
+    exp TIMESTAMPTZ,
+    like_rkey TEXT NOT NULL,
+    PRIMARY KEY (uri, val)
+);
+
+-- The expiry last given to each event's labels (end date + grace, in the
+-- event's time zone), so sync_labels can tell when an event's dates move.
+-- See labels::follow_event_exp.
+-- Migration for existing deployments:
+--   CREATE TABLE event_expiries (val TEXT PRIMARY KEY, exp TIMESTAMPTZ NOT NULL);
+CREATE TABLE event_expiries (
+    val TEXT PRIMARY KEY,
+    exp TIMESTAMPTZ NOT NULL
+);
+
+-- Every label each like gives (its edition's and/or its series'), so an
+-- unlike negates only what no other like of the account still gives. Likes
+-- from before this table fall back to the label log. See src/like_labels.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE like_labels (uri TEXT NOT NULL, like_rkey TEXT NOT NULL, val TEXT NOT NULL, PRIMARY KEY (uri, like_rkey, val));
+CREATE TABLE like_labels (
+    uri TEXT NOT NULL,
+    like_rkey TEXT NOT NULL,
+    val TEXT NOT NULL,
+    PRIMARY KEY (uri, like_rkey, val)
+);
+
+-- The writes of the last label sync and how far they got, so a sync that
+-- fails part-way is rolled forward (or abandoned) by the next. `plan` is a
+-- JSON sync_journal::Plan. Each sync deletes the journals already finished
+-- when it begins. See src/sync_journal.rs.
+-- Migration for existing deployments:
+--   CREATE TABLE sync_journal (id BIGSERIAL PRIMARY KEY, started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), plan TEXT NOT NULL, applied INT NOT NULL DEFAULT 0, commit_cid TEXT NOT NULL, abandoned BOOLEAN NOT NULL DEFAULT false, finished_at TIMESTAMPTZ);
+CREATE TABLE sync_journal (
+    id BIGSERIAL PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    plan TEXT NOT NULL,
+    applied INT NOT NULL DEFAULT 0,
+    commit_cid TEXT NOT NULL,
+    abandoned BOOLEAN NOT NULL DEFAULT false,
+    finished_at TIMESTAMPTZ
+);
+
 CREATE TABLE jetstream_cursor (cursor BIGINT NOT NULL);
 
 CREATE UNIQUE INDEX jetstream_cursor_single_row ON jetstream_cursor ((true));
@@ -0,0 +1,223 @@
+//! Account status from the firehose. A deleted account's labels are negated
+//! for good; a taken-down, suspended or deactivated one's are negated and set
+//! aside in `suspended_labels`, to be re-applied on reactivation for the
+//! likes that still exist. Likes deferred by `label_cap` wait out the
+//! suspension, and are dropped with everything else on deletion.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuspendedLabel {
+    pub val: String,
+    pub exp: Option<chrono::DateTime<chrono::Utc>>,
+    pub like_rkey: String,
+}
+
+/// Whether `did` has anything an account event can change: a label that is
+/// on, one set aside by `suspend`, or a deferred like. Jetstream sends
+/// account events for the whole network; everyone else is skipped on this
+/// one query.
+pub async fn tracked(db_conn: &mut sqlx::PgConnection, did: &str) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT
+            EXISTS (
+                SELECT 1 FROM current_labels
+                WHERE uri = $1 AND NOT neg
+                AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+            )
+            OR EXISTS (SELECT 1 FROM suspended_labels WHERE uri = $1)
+            OR EXISTS (SELECT 1 FROM deferred_labels WHERE uri = $1)
+            AS "tracked!"
+        "#,
+        did
+    )
+    .fetch_one(db_conn)
+    .await
+}
+
+/// Negate everything on `did`, keeping it for `restore` unless `purge`.
+/// Returns the number of labels negated.
+pub async fn suspend(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    did: &str,
+    purge: bool,
+) -> Result<u64, crate::labels::EmitError> {
+    if purge {
+        sqlx::query!(r#"DELETE FROM suspended_labels WHERE uri = $1"#, did)
+            .execute(&mut **tx)
+            .await?;
+        sqlx::query!(r#"DELETE FROM deferred_labels WHERE uri = $1"#, did)
+            .execute(&mut **tx)
+            .await?;
+    }
+
+    let mut n = 0;
+    for current in crate::labels::current(tx, did).await? {
+        if !purge {
+            sqlx::query!(
+                r#"
+                INSERT INTO suspended_labels (uri, val, exp, like_rkey)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (uri, val) DO UPDATE
+                SET exp = excluded.exp, like_rkey = excluded.like_rkey
+                "#,
+                current.uri,
+                current.val,
+                current.exp,
+                current.like_rkey,
+            )
+            .execute(&mut **tx)
+            .await?;
+        }
+        if crate::labels::negate(keypair, tx, src, &current)
+            .await?
+            .is_some()
+        {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/// What `suspend` set aside for `did` that has not expired since.
+pub async fn suspended(
+    db_conn: &mut sqlx::PgConnection,
+    did: &str,
+) -> Result<Vec<SuspendedLabel>, sqlx::Error> {
+    sqlx::query_as!(
+        SuspendedLabel,
+        r#"
+        SELECT val, exp, like_rkey
+        FROM suspended_labels
+        WHERE uri = $1 AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+        ORDER BY val
+        "#,
+        did
+    )
+    .fetch_all(db_conn)
+    .await
+}
+
+/// Re-apply `labels` (those of `suspended` whose likes survived) to `did`
+/// and forget the rest. Returns the number re-applied.
+pub async fn restore(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    did: &str,
+    labels: &[SuspendedLabel],
+) -> Result<u64, crate::labels::EmitError> {
+    sqlx::query!(r#"DELETE FROM suspended_labels WHERE uri = $1"#, did)
+        .execute(&mut **tx)
+        .await?;
+
+    let mut n = 0;
+    for label in labels {
+        if crate::labels::apply(
+            keypair,
+            tx,
+            src,
+            did,
+            &label.val,
+            label.exp,
+            &label.like_rkey,
+        )
+        .await?
+        .is_some()
+        {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // Takedown, then reactivation with one of the two likes gone; deletion
+    // leaves nothing to restore.
+    #[tokio::test]
+    #[ignore]
+    async fn suspend_then_restore() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let exp =
+            Some(chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0) + chrono::Days::new(30));
+
+        let mut tx = db_pool.begin().await.unwrap();
+        for (uri, val, like_rkey) in [
+            ("did:plc:a", "alpha", "3ka"),
+            ("did:plc:a", "beta", "3kb"),
+            ("did:plc:b", "alpha", "3kc"),
+        ] {
+            crate::labels::apply(&keypair, &mut tx, &src, uri, val, exp, like_rkey)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            suspend(&keypair, &mut tx, &src, "did:plc:a", false)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            suspend(&keypair, &mut tx, &src, "did:plc:b", true)
+                .await
+                .unwrap(),
+            1
+        );
+        tx.commit().await.unwrap();
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        assert!(tracked(&mut db_conn, "did:plc:a").await.unwrap());
+        assert!(!tracked(&mut db_conn, "did:plc:b").await.unwrap());
+        assert!(crate::labels::current(&mut db_conn, "did:plc:a")
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(suspended(&mut db_conn, "did:plc:b")
+            .await
+            .unwrap()
+            .is_empty());
+        let set_aside = suspended(&mut db_conn, "did:plc:a").await.unwrap();
+        assert_eq!(
+            set_aside,
+            vec![
+                SuspendedLabel {
+                    val: "alpha".to_string(),
+                    exp,
+                    like_rkey: "3ka".to_string(),
+                },
+                SuspendedLabel {
+                    val: "beta".to_string(),
+                    exp,
+                    like_rkey: "3kb".to_string(),
+                },
+            ]
+        );
+
+        let mut tx = db_pool.begin().await.unwrap();
+        assert_eq!(
+            restore(&keypair, &mut tx, &src, "did:plc:a", &set_aside[1..])
+                .await
+                .unwrap(),
+            1
+        );
+        tx.commit().await.unwrap();
+
+        let current = crate::labels::current(&mut db_conn, "did:plc:a")
+            .await
+            .unwrap();
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].val, "beta");
+        assert_eq!(current[0].like_rkey, "3kb");
+        assert!(suspended(&mut db_conn, "did:plc:a")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,161 @@
+//! Read-only lookups against a Bluesky AppView (public.api.bsky.app by
+//! default, or a local stand-in in tests), for what the firehose can't tell
+//! us after the fact.
+
+use atrium_api::types::Collection as _;
+
+pub const DEFAULT_URL: &str = "https://public.api.bsky.app";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("url: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("{status}: {body}")]
+    Status {
+        status: reqwest::StatusCode,
+        body: String,
+    },
+}
+
+#[derive(serde::Deserialize)]
+struct XrpcError {
+    error: String,
+}
+
+#[derive(Clone)]
+pub struct AppView {
+    pub reqwest_client: reqwest::Client,
+    pub url: url::Url,
+}
+
+impl AppView {
+    fn xrpc(&self, nsid: &str) -> Result<url::Url, Error> {
+        Ok(self.url.join(&format!("/xrpc/{nsid}"))?)
+    }
+
+    /// Whether `did`'s like `rkey` still exists.
+    pub async fn like_exists(&self, did: &str, rkey: &str) -> Result<bool, Error> {
+        let resp = self
+            .reqwest_client
+            .get(self.xrpc(atrium_api::com::atproto::repo::get_record::NSID)?)
+            .query(&[
+                ("repo", did),
+                ("collection", atrium_api::app::bsky::feed::Like::NSID),
+                ("rkey", rkey),
+            ])
+            .send()
+            .await?;
+        if resp.status().is_success() {
+            return Ok(true);
+        }
+        // Only RecordNotFound says the like is gone. Any other error, 400s
+        // included (a bad request, an upstream failure), says nothing about
+        // it, and taking it for gone would negate labels wholesale.
+        let status = resp.status();
+        let body = resp.text().await?;
+        if status == reqwest::StatusCode::BAD_REQUEST
+            && serde_json::from_str::<XrpcError>(&body)
+                .is_ok_and(|error| error.error == "RecordNotFound")
+        {
+            return Ok(false);
+        }
+        Err(Error::Status { status, body })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct GetLikes {
+    likes: Vec<GetLikesLike>,
+    cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct GetLikesLike {
+    actor: GetLikesActor,
+}
+
+#[derive(serde::Deserialize)]
+struct GetLikesActor {
+    did: String,
+}
+
+impl AppView {
+    /// The DIDs of everyone who likes the post at `uri`, paging through
+    /// `app.bsky.feed.getLikes`. The AppView gives no like record keys.
+    pub async fn likers(&self, uri: &str) -> Result<Vec<String>, Error> {
+        let url = self.xrpc(atrium_api::app::bsky::feed::get_likes::NSID)?;
+        let mut likers = vec![];
+        let mut cursor = None;
+        loop {
+            let mut req = self
+                .reqwest_client
+                .get(url.clone())
+                .query(&[("uri", uri), ("limit", "100")]);
+            if let Some(cursor) = &cursor {
+                req = req.query(&[("cursor", cursor)]);
+            }
+            let page = req
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<GetLikes>()
+                .await?;
+            let empty = page.likes.is_empty();
+            likers.extend(page.likes.into_iter().map(|like| like.actor.did));
+            match page.cursor {
+                Some(next) if !empty => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(likers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn like_exists_against_a_stand_in() {
+        let app = axum::Router::new().route(
+            "/xrpc/com.atproto.repo.getRecord",
+            axum::routing::get(
+                |axum::extract::Query(params): axum::extract::Query<
+                    std::collections::HashMap<String, String>,
+                >| async move {
+                    if params["rkey"] == "3kliked" {
+                        (axum::http::StatusCode::OK, "{}")
+                    } else if params["rkey"] == "not an rkey" {
+                        (
+                            axum::http::StatusCode::BAD_REQUEST,
+                            r#"{"error":"InvalidRequest"}"#,
+                        )
+                    } else {
+                        (
+                            axum::http::StatusCode::BAD_REQUEST,
+                            r#"{"error":"RecordNotFound"}"#,
+                        )
+                    }
+                },
+            ),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let appview = AppView {
+            reqwest_client: reqwest::Client::new(),
+            url: url::Url::parse(&format!("http://{addr}")).unwrap(),
+        };
+        assert!(appview.like_exists("did:plc:a", "3kliked").await.unwrap());
+        assert!(!appview.like_exists("did:plc:a", "3kunliked").await.unwrap());
+        assert!(matches!(
+            appview.like_exists("did:plc:a", "not an rkey").await,
+            Err(Error::Status { status, .. }) if status == reqwest::StatusCode::BAD_REQUEST
+        ));
+    }
+}
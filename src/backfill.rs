@@ -0,0 +1,658 @@
+//! Backfill and reconciliation: catch up on likes the firehose can no longer
+//! replay (the ingester was down past Jetstream's retention), and check that
+//! labels and likes agree. For each event post, the AppView's `getLikes` is
+//! diffed against the labels a like of it gives (the edition's and/or the
+//! series'; see `like_labels`):
+//!
+//! - a liker without one of them is *unlabeled*; fixing applies the label
+//!   once its like record is found in its repo (the AppView gives no rkeys,
+//!   and unlikes are matched by rkey), recording it in `like_labels` as the
+//!   firehose would;
+//! - a label whose liker is gone from the list is *orphaned*, once the
+//!   AppView also confirms that its like record is gone; fixing takes off
+//!   what an unlike of it would (a series label another like still gives
+//!   stays);
+//! - with `check_rkeys`, a label on a liker whose `like_rkey` is not (or no
+//!   longer) a like record has a *stale rkey*; fixing re-issues it with the
+//!   like's actual rkey.
+//!
+//! Unlabeled and orphaned are what a firehose gap leaves behind. A stale rkey
+//! is the delete path's blind spot: the next unlike won't find the label.
+//!
+//! Opted-out and suspended accounts are left alone, as are labels applied by
+//! hand and ones issued in the last `SETTLE`, which the AppView may not have
+//! indexed yet.
+
+use sqlx::Acquire as _;
+
+const SETTLE: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+
+/// How far back into a liker's likes (100 a page, newest first) to look for
+/// the record behind a like the AppView lists.
+const MAX_LIKE_PAGES: usize = 10;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("appview: {0}")]
+    AppView(#[from] crate::appview::Error),
+
+    #[error("did_doc: {0}")]
+    DidDoc(#[from] crate::did_doc::Error),
+
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("url: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("emit: {0}")]
+    Emit(#[from] crate::labels::EmitError),
+
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+}
+
+/// An event's post and the labels its likes get.
+#[derive(Debug, Clone)]
+pub struct EventPost {
+    pub label_id: String,
+    /// `at://` URI of the post.
+    pub uri: String,
+    /// `(val, exp)` for each label a like of the post gives, as
+    /// `like_labels::for_event` has it.
+    pub labels: Vec<(String, Option<chrono::DateTime<chrono::Utc>>)>,
+}
+
+/// Discrepancies found for one event, fixed or not.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Outcome {
+    pub likers: u64,
+    pub labeled: u64,
+    pub unlabeled: u64,
+    pub orphaned: u64,
+    pub stale_rkey: u64,
+    /// Unlabeled or stale-rkey likes left as they were when fixing, as their
+    /// like record could not be found.
+    pub unresolved: u64,
+    /// Unlabeled likes deferred by the per-user cap when fixing; see
+    /// `label_cap`.
+    pub deferred: u64,
+}
+
+impl Outcome {
+    pub fn is_clean(&self) -> bool {
+        self.unlabeled == 0 && self.orphaned == 0 && self.stale_rkey == 0
+    }
+
+    pub fn add(&mut self, other: &Outcome) {
+        self.likers += other.likers;
+        self.labeled += other.labeled;
+        self.unlabeled += other.unlabeled;
+        self.orphaned += other.orphaned;
+        self.stale_rkey += other.stale_rkey;
+        self.unresolved += other.unresolved;
+        self.deferred += other.deferred;
+    }
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} liker(s), {} label(s): {} unlabeled, {} orphaned, {} stale rkey(s)",
+            self.likers, self.labeled, self.unlabeled, self.orphaned, self.stale_rkey
+        )?;
+        if self.unresolved > 0 {
+            write!(f, ", {} unresolved", self.unresolved)?;
+        }
+        if self.deferred > 0 {
+            write!(f, ", {} deferred", self.deferred)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ListRecords {
+    records: Vec<ListRecordsRecord>,
+    cursor: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ListRecordsRecord {
+    uri: String,
+    value: serde_json::Value,
+}
+
+pub struct Backfill<'a> {
+    pub db_pool: &'a sqlx::PgPool,
+    pub appview: &'a crate::appview::AppView,
+    pub resolver: &'a crate::did_doc::Resolver,
+    pub keypair: &'a atrium_crypto::keypair::Secp256k1Keypair,
+    pub src: &'a atrium_api::types::string::Did,
+    pub max_labels_per_user: Option<usize>,
+    /// Fix what is found, rather than only report it.
+    pub fix: bool,
+    /// Also check each liker's label against its like record, one AppView
+    /// request per label.
+    pub check_rkeys: bool,
+}
+
+impl Backfill<'_> {
+    /// Go over every post in turn; one failing doesn't stop the rest.
+    pub async fn run(&self, posts: &[EventPost]) -> Vec<(String, Result<Outcome, Error>)> {
+        let mut outcomes = vec![];
+        for post in posts {
+            outcomes.push((post.label_id.clone(), self.event(post).await));
+        }
+        outcomes
+    }
+
+    pub async fn event(&self, post: &EventPost) -> Result<Outcome, Error> {
+        let mut outcome = Outcome::default();
+        let likers = self
+            .appview
+            .likers(&post.uri)
+            .await?
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        outcome.likers = likers.len() as u64;
+
+        let vals = post
+            .labels
+            .iter()
+            .map(|(val, _)| val.clone())
+            .collect::<Vec<_>>();
+        let mut db_conn = self.db_pool.acquire().await?;
+        let labeled = sqlx::query!(
+            r#"
+            SELECT uri, val, exp, like_rkey, seq, cts
+            FROM current_labels
+            WHERE val = ANY($1) AND NOT neg AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+            ORDER BY uri, val
+            "#,
+            &vals,
+        )
+        .fetch_all(&mut *db_conn)
+        .await?;
+        outcome.labeled = labeled.len() as u64;
+        let on = labeled
+            .iter()
+            .map(|row| (row.uri.as_str(), row.val.as_str()))
+            .collect::<std::collections::HashSet<_>>();
+
+        let mut likers_sorted = likers.iter().collect::<Vec<_>>();
+        likers_sorted.sort();
+        for liker in likers_sorted {
+            let missing = post
+                .labels
+                .iter()
+                .filter(|(val, _)| !on.contains(&(liker.as_str(), val.as_str())))
+                .collect::<Vec<_>>();
+            if missing.is_empty()
+                || crate::opt_out::is_opted_out(&mut db_conn, liker).await?
+                || !crate::accounts::suspended(&mut db_conn, liker)
+                    .await?
+                    .is_empty()
+            {
+                continue;
+            }
+            outcome.unlabeled += missing.len() as u64;
+            if !self.fix {
+                continue;
+            }
+            let Some(like_rkey) = self.like_rkey(liker, &post.uri).await? else {
+                log::warn!("{liker} likes {}, but no like record was found", post.uri);
+                outcome.unresolved += 1;
+                continue;
+            };
+            let mut tx = db_conn.begin().await?;
+            for (val, exp) in missing {
+                if !crate::like_labels::record(&mut tx, liker, &like_rkey, val).await? {
+                    continue;
+                }
+                let label = crate::labels::new_label(self.src, liker, val, false, *exp);
+                match self.max_labels_per_user {
+                    Some(max) => {
+                        let capped =
+                            crate::label_cap::emit(self.keypair, &mut tx, &label, &like_rkey, max)
+                                .await?;
+                        outcome.deferred += capped.deferred as u64;
+                    }
+                    None => {
+                        crate::labels::emit(self.keypair, &mut tx, &label, &like_rkey).await?;
+                    }
+                }
+            }
+            tx.commit().await?;
+        }
+
+        let settled = chrono::Utc::now() - SETTLE;
+        for row in labeled {
+            if row.like_rkey == crate::labels::ADMIN_LIKE_RKEY || row.cts > settled {
+                continue;
+            }
+
+            // A liker's series label may be held by its like of another
+            // edition, so it is checked against the like that holds it.
+            if likers.contains(&row.uri) {
+                if !self.check_rkeys || self.appview.like_exists(&row.uri, &row.like_rkey).await? {
+                    continue;
+                }
+                outcome.stale_rkey += 1;
+                if !self.fix {
+                    continue;
+                }
+                let Some(like_rkey) = self.like_rkey(&row.uri, &post.uri).await? else {
+                    outcome.unresolved += 1;
+                    continue;
+                };
+                let mut tx = db_conn.begin().await?;
+                crate::like_labels::record(&mut tx, &row.uri, &like_rkey, &row.val).await?;
+                crate::like_labels::forget(&mut tx, &row.uri, &row.like_rkey, &row.val).await?;
+                crate::labels::apply(
+                    self.keypair,
+                    &mut tx,
+                    self.src,
+                    &row.uri,
+                    &row.val,
+                    row.exp,
+                    &like_rkey,
+                )
+                .await?;
+                tx.commit().await?;
+                continue;
+            }
+
+            if self.appview.like_exists(&row.uri, &row.like_rkey).await? {
+                continue;
+            }
+            outcome.orphaned += 1;
+            if !self.fix {
+                continue;
+            }
+            let mut tx = db_conn.begin().await?;
+            let gone = crate::like_labels::unlike(&mut tx, &row.uri, &row.like_rkey).await?;
+            let mut negated = false;
+            for current in crate::labels::current(&mut tx, &row.uri).await? {
+                if gone.contains(&current.val) {
+                    crate::labels::negate(self.keypair, &mut tx, self.src, &current).await?;
+                    negated = true;
+                }
+            }
+            if let (Some(max), true) = (self.max_labels_per_user, negated) {
+                crate::label_cap::promote(self.keypair, &mut tx, self.src, &row.uri, max).await?;
+            }
+            tx.commit().await?;
+        }
+
+        Ok(outcome)
+    }
+
+    /// The rkey of `did`'s like of `subject`, from `did`'s own repo.
+    async fn like_rkey(&self, did: &str, subject: &str) -> Result<Option<String>, Error> {
+        use atrium_api::types::Collection as _;
+
+        let Ok(did) = atrium_api::types::string::Did::new(did.to_string()) else {
+            return Ok(None);
+        };
+        let pds = self.resolver.pds(&did).await?;
+        let url = pds.join(&format!(
+            "/xrpc/{}",
+            atrium_api::com::atproto::repo::list_records::NSID
+        ))?;
+
+        let mut cursor = None;
+        for _ in 0..MAX_LIKE_PAGES {
+            let mut req = self.appview.reqwest_client.get(url.clone()).query(&[
+                ("repo", did.as_str()),
+                ("collection", atrium_api::app::bsky::feed::Like::NSID),
+                ("limit", "100"),
+            ]);
+            if let Some(cursor) = &cursor {
+                req = req.query(&[("cursor", cursor)]);
+            }
+            let page = req
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<ListRecords>()
+                .await?;
+            if let Some(record) = page.records.iter().find(|record| {
+                record
+                    .value
+                    .pointer("/subject/uri")
+                    .and_then(|uri| uri.as_str())
+                    == Some(subject)
+            }) {
+                return Ok(record.uri.rsplit('/').next().map(str::to_string));
+            }
+            match page.cursor {
+                Some(next) if !page.records.is_empty() => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POST: &str = "at://did:plc:labeler/app.bsky.feed.post/3kpost";
+
+    /// AppView, PLC directory and PDS in one: `likers` like `POST`; the repo
+    /// of each DID in `repos` holds the given (rkey, subject) likes; every
+    /// DID's document points at this server as its PDS.
+    async fn serve(likers: &[&str], repos: &[(&str, &[(&str, &str)])]) -> url::Url {
+        use axum::extract::{Path, Query};
+        use std::collections::HashMap;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+
+        let get_likes = serde_json::json!({
+            "uri": POST,
+            "likes": likers
+                .iter()
+                .map(|did| serde_json::json!({
+                    "indexedAt": "2025-01-01T00:00:00Z",
+                    "createdAt": "2025-01-01T00:00:00Z",
+                    "actor": { "did": did, "handle": "handle.invalid" },
+                }))
+                .collect::<Vec<_>>(),
+        });
+        let repos = repos
+            .iter()
+            .map(|(did, likes)| {
+                (
+                    did.to_string(),
+                    likes
+                        .iter()
+                        .map(|(rkey, subject)| (rkey.to_string(), subject.to_string()))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .collect::<HashMap<_, _>>();
+        let repos = std::sync::Arc::new(repos);
+
+        let app = axum::Router::new()
+            .route(
+                "/xrpc/app.bsky.feed.getLikes",
+                axum::routing::get(move || async move { axum::Json(get_likes) }),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.getRecord",
+                axum::routing::get({
+                    let repos = repos.clone();
+                    move |Query(params): Query<HashMap<String, String>>| async move {
+                        let found = repos
+                            .get(&params["repo"])
+                            .is_some_and(|likes| likes.iter().any(|(rkey, _)| *rkey == params["rkey"]));
+                        if found {
+                            (axum::http::StatusCode::OK, "{}")
+                        } else {
+                            (axum::http::StatusCode::BAD_REQUEST, r#"{"error":"RecordNotFound"}"#)
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/xrpc/com.atproto.repo.listRecords",
+                axum::routing::get({
+                    let repos = repos.clone();
+                    move |Query(params): Query<HashMap<String, String>>| async move {
+                        let repo = params["repo"].clone();
+                        let records = repos
+                            .get(&repo)
+                            .into_iter()
+                            .flatten()
+                            .map(|(rkey, subject)| serde_json::json!({
+                                "uri": format!("at://{repo}/app.bsky.feed.like/{rkey}"),
+                                "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm",
+                                "value": {
+                                    "$type": "app.bsky.feed.like",
+                                    "subject": { "uri": subject, "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm" },
+                                    "createdAt": "2025-01-01T00:00:00Z",
+                                },
+                            }))
+                            .collect::<Vec<_>>();
+                        axum::Json(serde_json::json!({ "records": records }))
+                    }
+                }),
+            )
+            .route(
+                "/{did}",
+                axum::routing::get({
+                    let base = base.clone();
+                    move |Path(did): Path<String>| async move {
+                        axum::Json(serde_json::json!({
+                            "id": did,
+                            "service": [{
+                                "id": "#atproto_pds",
+                                "type": "AtprotoPersonalDataServer",
+                                "serviceEndpoint": base,
+                            }],
+                        }))
+                    }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url::Url::parse(&base).unwrap()
+    }
+
+    fn label_at(
+        cts: chrono::DateTime<chrono::Utc>,
+        uri: &str,
+    ) -> atrium_api::com::atproto::label::defs::Label {
+        let mut label = crate::labels::new_label(
+            &atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap(),
+            uri,
+            "examplecon-mmxxv",
+            false,
+            Some(chrono::Utc::now() + chrono::Days::new(30)),
+        );
+        label.cts = atrium_api::types::string::Datetime::new(cts.fixed_offset());
+        label
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // Reported first, then fixed, then clean but for the like that can't be
+    // found.
+    #[tokio::test]
+    #[ignore]
+    async fn reconciles_both_ways() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let long_ago = chrono::Utc::now() - chrono::Days::new(2);
+
+        let url = serve(
+            &[
+                "did:plc:a",
+                "did:plc:b",
+                "did:plc:c",
+                "did:plc:d",
+                "did:plc:i",
+            ],
+            &[
+                (
+                    "did:plc:a",
+                    &[
+                        ("3kother", "at://did:plc:x/app.bsky.feed.post/3kx"),
+                        ("3ka", POST),
+                    ],
+                ),
+                ("did:plc:b", &[("3kb", POST)]),
+                ("did:plc:c", &[("3kc", POST)]),
+                // did:plc:d's like is listed but not in its repo.
+                ("did:plc:h", &[("3kh", POST)]),
+                ("did:plc:i", &[("3ki", POST)]),
+            ],
+        )
+        .await;
+
+        let mut tx = db_pool.begin().await.unwrap();
+        for (uri, like_rkey, cts) in [
+            ("did:plc:b", "3kb", long_ago),
+            // Unliked while we were down.
+            ("did:plc:e", "3ke", long_ago),
+            // By hand.
+            ("did:plc:f", crate::labels::ADMIN_LIKE_RKEY, long_ago),
+            // Too new for the AppView to know of.
+            ("did:plc:g", "3kg", chrono::Utc::now()),
+            // Missing from getLikes, but the record is there.
+            ("did:plc:h", "3kh", long_ago),
+            // Liked again; the label still points at the first like.
+            ("did:plc:i", "3kold", long_ago),
+        ] {
+            crate::labels::emit(&keypair, &mut tx, &label_at(cts, uri), like_rkey)
+                .await
+                .unwrap();
+        }
+        crate::opt_out::opt_out(&keypair, &mut tx, &src, "did:plc:c", "3kblock")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        let appview = crate::appview::AppView {
+            reqwest_client: reqwest::Client::new(),
+            url: url.clone(),
+        };
+        let resolver = crate::did_doc::Resolver {
+            reqwest_client: reqwest::Client::new(),
+            plc_url: url,
+        };
+        let mut backfill = Backfill {
+            db_pool: &db_pool,
+            appview: &appview,
+            resolver: &resolver,
+            keypair: &keypair,
+            src: &src,
+            max_labels_per_user: None,
+            fix: false,
+            check_rkeys: true,
+        };
+        let post = EventPost {
+            label_id: "examplecon-mmxxv".to_string(),
+            uri: POST.to_string(),
+            labels: vec![(
+                "examplecon-mmxxv".to_string(),
+                Some(
+                    chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0)
+                        + chrono::Days::new(30),
+                ),
+            )],
+        };
+        let on = || async {
+            let mut db_conn = db_pool.acquire().await.unwrap();
+            crate::labels::current_with_vals(&mut db_conn, std::slice::from_ref(&post.label_id))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|c| (c.uri, c.like_rkey))
+                .collect::<Vec<_>>()
+        };
+        let before = on().await;
+
+        let drift = Outcome {
+            likers: 5,
+            labeled: 6,
+            unlabeled: 2,
+            orphaned: 1,
+            stale_rkey: 1,
+            ..Default::default()
+        };
+        assert_eq!(backfill.event(&post).await.unwrap(), drift);
+        assert_eq!(on().await, before);
+
+        backfill.fix = true;
+        assert_eq!(
+            backfill.event(&post).await.unwrap(),
+            Outcome {
+                unresolved: 1,
+                ..drift
+            }
+        );
+        assert_eq!(
+            on().await,
+            [
+                ("did:plc:a", "3ka"),
+                ("did:plc:b", "3kb"),
+                ("did:plc:f", crate::labels::ADMIN_LIKE_RKEY),
+                ("did:plc:g", "3kg"),
+                ("did:plc:h", "3kh"),
+                ("did:plc:i", "3ki"),
+            ]
+            .map(|(uri, like_rkey)| (uri.to_string(), like_rkey.to_string()))
+        );
+
+        assert_eq!(
+            backfill.event(&post).await.unwrap(),
+            Outcome {
+                likers: 5,
+                labeled: 6,
+                unlabeled: 1,
+                unresolved: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    // What a backfill labels is recorded like a firehose like: its unlike
+    // takes off both the edition's and the series' label.
+    #[tokio::test]
+    #[ignore]
+    async fn backfilled_like_unlikes() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let url = serve(&["did:plc:a"], &[("did:plc:a", &[("3ka", POST)])]).await;
+        let appview = crate::appview::AppView {
+            reqwest_client: reqwest::Client::new(),
+            url: url.clone(),
+        };
+        let resolver = crate::did_doc::Resolver {
+            reqwest_client: reqwest::Client::new(),
+            plc_url: url,
+        };
+        let backfill = Backfill {
+            db_pool: &db_pool,
+            appview: &appview,
+            resolver: &resolver,
+            keypair: &keypair,
+            src: &src,
+            max_labels_per_user: None,
+            fix: true,
+            check_rkeys: false,
+        };
+        let post = EventPost {
+            label_id: "examplecon-mmxxv".to_string(),
+            uri: POST.to_string(),
+            labels: vec![
+                (
+                    "examplecon-mmxxv".to_string(),
+                    Some(chrono::Utc::now() + chrono::Days::new(30)),
+                ),
+                ("series-examplecon".to_string(), None),
+            ],
+        };
+        assert_eq!(
+            backfill.event(&post).await.unwrap(),
+            Outcome {
+                likers: 1,
+                unlabeled: 2,
+                ..Default::default()
+            }
+        );
+
+        let mut tx = db_pool.begin().await.unwrap();
+        assert_eq!(
+            crate::like_labels::unlike(&mut tx, "did:plc:a", "3ka")
+                .await
+                .unwrap(),
+            vec!["examplecon-mmxxv", "series-examplecon"]
+        );
+    }
+}
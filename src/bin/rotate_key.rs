@@ -0,0 +1,105 @@
+//! Switch the labeler to a new signing key, in two steps:
+//!
+//!     rotate_key <labeler did> <new key file>
+//!     rotate_key <labeler did> --resign
+//!
+//! Publish the new key as the `#atproto_label` verification method in the
+//! labeler's DID document first (a PLC operation for did:plc); both steps
+//! refuse to go on until the document says so. The first installs the new
+//! key at `keypair_path` (the old one is kept alongside). Then restart the
+//! ingester, which only reads its key at startup and signs with the old one
+//! until then. `--resign` re-issues the labels in effect under the installed
+//! key; run it after the restart, so it also covers whatever the ingester
+//! signed with the old key in between.
+
+use atrium_crypto::keypair::Did as _;
+use bsky_event_ingester::*;
+use sqlx::Connection as _;
+
+#[derive(serde::Deserialize)]
+struct Config {
+    keypair_path: String,
+    postgres_url: String,
+    plc_url: url::Url,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    env_logger::init();
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (did, new_keypair_path) = match &args[..] {
+        [did, flag] if flag == "--resign" => (did, None),
+        [did, path] => (did, Some(path)),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "usage: rotate_key <labeler did> <new key file>\n       rotate_key <labeler did> --resign"
+            ))
+        }
+    };
+    let did = atrium_api::types::string::Did::new(did.clone()).map_err(anyhow::Error::msg)?;
+
+    let config: Config = config::Config::builder()
+        .add_source(config::File::with_name("config.toml"))
+        .set_default("keypair_path", "signing.key")?
+        .set_default("plc_url", did_doc::DEFAULT_PLC_URL)?
+        .build()?
+        .try_deserialize()?;
+    let keypair_path = std::path::Path::new(&config.keypair_path);
+
+    let key = std::fs::read(new_keypair_path.map_or(keypair_path, std::path::Path::new))?;
+    let keypair = atrium_crypto::keypair::Secp256k1Keypair::import(&key)?;
+
+    let resolver = did_doc::Resolver {
+        reqwest_client: reqwest::Client::new(),
+        plc_url: config.plc_url,
+    };
+    resolver
+        .verify_label_key(&did, &keypair)
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}; update the DID document first"))?;
+
+    if new_keypair_path.is_none() {
+        let mut db_conn = sqlx::PgConnection::connect(&config.postgres_url).await?;
+        let mut tx = db_conn.begin().await?;
+        let n = labels::resign_current(&keypair, &mut tx, &did).await?;
+        tx.commit().await?;
+        eprintln!("re-signed {n} label(s) in effect");
+        return Ok(());
+    }
+
+    // Never without a key at keypair_path: the new one is written in full
+    // (owner-only, synced) beside it, the old one linked to its `.old` name,
+    // and only then is the new one renamed over it.
+    let tmp = keypair_path.with_extension("new");
+    {
+        use std::io::Write as _;
+        use std::os::unix::fs::OpenOptionsExt as _;
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&tmp)
+            .map_err(|e| anyhow::anyhow!("{}: {e}", tmp.display()))?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+    }
+    if keypair_path.exists() {
+        let old = keypair_path.with_extension(format!(
+            "{}.old",
+            chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+        ));
+        std::fs::hard_link(keypair_path, &old)?;
+        eprintln!("old key kept at {}", old.display());
+    }
+    std::fs::rename(&tmp, keypair_path)?;
+    eprintln!(
+        "{} installed at {}. Next:\n  1. restart the ingester; it signs with the old key until then\n  2. rotate_key {} --resign, to re-issue the labels in effect (including any signed before the restart) under the new key",
+        keypair.did(),
+        keypair_path.display(),
+        did.as_str()
+    );
+
+    Ok(())
+}
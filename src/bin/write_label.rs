@@ -1,39 +1,468 @@
+//! Label admin tool, for moderators. Reads the same `config.toml` as the
+//! ingester (`keypair_path`, `postgres_url`, `events_url`), plus
+//! `labeler_did`, the `src` of the labels it signs.
+//!
+//!     write_label apply <did | handle> <event id | val> [--exp <rfc3339>] [--like-rkey <rkey>] [--dry-run]
+//!     write_label negate <did | handle> <event id | val> [--dry-run]
+//!     write_label list <did | handle>
+//!     write_label bulk <file.csv | file.jsonl> [--dry-run]
+//!     write_label audit [--key <did:key>]...
+//!     write_label emit <like rkey> < label.json
+//!
+//! An event id is turned into its label val the way the ingester does it; a
+//! val is left as it is. Applied labels expire with the event, looked up in
+//! the events feed, unless `--exp` says otherwise. `--dry-run` prints the
+//! signed labels instead of writing them. A handle is looked up in the
+//! ingester's handle cache (see `handles`), not resolved over the network.
+//!
+//! `audit` re-verifies every stored payload against the configured key and
+//! any `--key` given (say, the keys before a rotation), checks it against the
+//! row's indexed columns, and reports rows that fail. It exits non-zero if
+//! any do.
+//!
+//! Bulk files have one operation per line: `action,did,target[,exp]` for CSV
+//! (a first line starting with `action` is a header), or
+//! `{"action": …, "did": …, "target": …, "exp": …}` for JSONL.
+
+use atrium_crypto::keypair::Did as _;
 use bsky_event_ingester::*;
 use sqlx::Connection as _;
 
+const USAGE: &str = "usage: write_label apply|negate <did | handle> <event id | val> [--exp <rfc3339>] [--like-rkey <rkey>] [--dry-run]
+       write_label list <did | handle>
+       write_label bulk <file.csv | file.jsonl> [--dry-run]
+       write_label audit [--key <did:key>]...
+       write_label emit <like rkey> < label.json";
+
 #[derive(serde::Deserialize)]
 struct Config {
     keypair_path: String,
     postgres_url: String,
+    events_url: String,
+    labeler_did: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Apply,
+    Negate,
+}
+
+impl std::str::FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "apply" => Ok(Action::Apply),
+            "negate" => Ok(Action::Negate),
+            _ => Err(anyhow::anyhow!("unknown action {s:?}")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
+struct Op {
+    action: Action,
+    did: String,
+    /// An event id or a label val.
+    target: String,
+    #[serde(default)]
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+fn parse_csv_line(line: &str) -> Result<Op, anyhow::Error> {
+    let (action, did, target, exp) = match line.split(',').map(str::trim).collect::<Vec<_>>()[..] {
+        [action, did, target] => (action, did, target, ""),
+        [action, did, target, exp] => (action, did, target, exp),
+        _ => return Err(anyhow::anyhow!("expected action,did,target[,exp]")),
+    };
+    Ok(Op {
+        action: action.parse()?,
+        did: did.to_string(),
+        target: target.to_string(),
+        exp: (!exp.is_empty()).then(|| exp.parse()).transpose()?,
+    })
+}
+
+fn parse_bulk(path: &std::path::Path, contents: &str) -> Result<Vec<Op>, anyhow::Error> {
+    let jsonl = path.extension().is_some_and(|ext| ext == "jsonl");
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .filter(|(i, line)| jsonl || *i > 0 || !line.starts_with("action"))
+        .map(|(i, line)| {
+            if jsonl {
+                serde_json::from_str(line).map_err(anyhow::Error::from)
+            } else {
+                parse_csv_line(line)
+            }
+            .map_err(|e| anyhow::anyhow!("{}:{}: {e}", path.display(), i + 1))
+        })
+        .collect()
+}
+
+/// `account` as a DID: as it is if it is one, else from the handle cache.
+async fn resolve_account(
+    db_conn: &mut sqlx::PgConnection,
+    account: &str,
+) -> Result<String, anyhow::Error> {
+    if account.starts_with("did:") {
+        return Ok(account.to_string());
+    }
+    handles::did(db_conn, account).await?.ok_or_else(|| {
+        anyhow::anyhow!("{account}: no such handle in the cache (pass the DID instead)")
+    })
+}
+
+/// An `Op` with its account and expiry looked up.
+struct Resolved {
+    action: Action,
+    did: String,
+    /// `did` for messages.
+    who: String,
+    val: String,
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+struct Tool {
+    keypair: atrium_crypto::keypair::Secp256k1Keypair,
+    src: Option<atrium_api::types::string::Did>,
+    events_url: String,
+    events: Option<std::collections::HashMap<String, events::AssociatedEvent>>,
+    dry_run: bool,
+}
+
+impl Tool {
+    fn src(&self) -> Result<&atrium_api::types::string::Did, anyhow::Error> {
+        self.src
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("set labeler_did in config.toml"))
+    }
+
+    /// The event's expiry, if `val` belongs to an event in the feed.
+    async fn event_exp(
+        &mut self,
+        val: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, anyhow::Error> {
+        if self.events.is_none() {
+            self.events =
+                Some(events::fetch_events(&reqwest::Client::new(), &self.events_url).await?);
+        }
+        Ok(self
+            .events
+            .as_ref()
+            .unwrap()
+            .values()
+            .find(|assoc_event| assoc_event.label_id == val)
+            .map(|assoc_event| assoc_event.event.end_time() + events::EXPIRY_DATE_GRACE_PERIOD))
+    }
+
+    fn print_signed(
+        &self,
+        label: &atrium_api::com::atproto::label::defs::Label,
+    ) -> Result<(), anyhow::Error> {
+        let signed: atrium_api::com::atproto::label::defs::Label =
+            serde_ipld_dagcbor::from_slice(&labels::sign_to_payload(&self.keypair, label)?)?;
+        println!("{}", serde_json::to_string(&signed)?);
+        Ok(())
+    }
+
+    /// Everything `run` needs from outside the labels tables: the DID, and
+    /// for an apply the expiry, which may take fetching the events feed.
+    /// Done before the transaction that writes, so it doesn't hold the labels
+    /// lock across the fetch.
+    async fn resolve(
+        &mut self,
+        db_conn: &mut sqlx::PgConnection,
+        op: &Op,
+    ) -> Result<Resolved, anyhow::Error> {
+        let val = events::id_to_label(&op.target);
+        let did = resolve_account(db_conn, &op.did).await?;
+        let who = handles::describe(db_conn, &did).await;
+        let exp = match (op.action, op.exp) {
+            (Action::Negate, _) => None,
+            (Action::Apply, Some(exp)) => Some(exp),
+            (Action::Apply, None) => {
+                let exp = self.event_exp(&val).await?;
+                if exp.is_none() {
+                    eprintln!("{val}: not an event in the feed, label will not expire");
+                }
+                exp
+            }
+        };
+        Ok(Resolved {
+            action: op.action,
+            did,
+            who,
+            val,
+            exp,
+        })
+    }
+
+    async fn run(
+        &self,
+        tx: &mut sqlx::PgTransaction<'_>,
+        op: &Resolved,
+        like_rkey: &str,
+    ) -> Result<(), anyhow::Error> {
+        let Resolved {
+            action,
+            did,
+            who,
+            val,
+            exp,
+        } = op;
+        match action {
+            Action::Apply => {
+                if self.dry_run {
+                    return self.print_signed(&labels::new_label(
+                        self.src()?,
+                        did,
+                        val,
+                        false,
+                        *exp,
+                    ));
+                }
+                match labels::apply(&self.keypair, tx, self.src()?, did, val, *exp, like_rkey)
+                    .await?
+                {
+                    Some(seq) => println!("{seq}\tapplied {val} to {who}"),
+                    None => eprintln!("{val} already on {who}, nothing emitted"),
+                }
+            }
+            Action::Negate => {
+                let Some(current) = labels::current(tx, did)
+                    .await?
+                    .into_iter()
+                    .find(|current| current.val == *val)
+                else {
+                    eprintln!("{val} not in effect on {who}, nothing to negate");
+                    return Ok(());
+                };
+                if self.dry_run {
+                    return self.print_signed(&labels::new_label(
+                        self.src()?,
+                        did,
+                        val,
+                        true,
+                        None,
+                    ));
+                }
+                if let Some(seq) = labels::negate(&self.keypair, tx, self.src()?, &current).await? {
+                    println!("{seq}\tnegated {val} on {who}");
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
 
-    let [rkey] = &std::env::args().skip(1).collect::<Vec<_>>()[..] else {
-        return Err(anyhow::anyhow!("not enough arguments"));
-    };
-    let rkey = atrium_api::types::string::RecordKey::new(rkey.to_string()).unwrap();
-
-    let label = serde_json::from_reader(std::io::stdin())?;
+    let mut positional = vec![];
+    let mut exp = None;
+    let mut like_rkey = labels::ADMIN_LIKE_RKEY.to_string();
+    let mut dry_run = false;
+    let mut keys = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => dry_run = true,
+            "--exp" => exp = Some(args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?.parse()?),
+            "--like-rkey" => like_rkey = args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?,
+            "--key" => keys.push(args.next().ok_or_else(|| anyhow::anyhow!(USAGE))?),
+            _ => positional.push(arg),
+        }
+    }
 
     let config: Config = config::Config::builder()
         .add_source(config::File::with_name("config.toml"))
         .set_default("keypair_path", "signing.key")?
+        .set_default("events_url", "https://data.cons.fyi/current.jsonl")?
         .build()?
         .try_deserialize()?;
 
-    let keypair =
-        atrium_crypto::keypair::Secp256k1Keypair::import(&std::fs::read(&config.keypair_path)?)?;
+    let mut tool = Tool {
+        keypair: atrium_crypto::keypair::Secp256k1Keypair::import(&std::fs::read(
+            &config.keypair_path,
+        )?)?,
+        src: config
+            .labeler_did
+            .map(atrium_api::types::string::Did::new)
+            .transpose()
+            .map_err(anyhow::Error::msg)?,
+        events_url: config.events_url,
+        events: None,
+        dry_run,
+    };
 
     let mut db_conn = sqlx::PgConnection::connect(&config.postgres_url).await?;
 
-    let mut tx = db_conn.begin().await?;
-    let seq = labels::emit(&keypair, &mut tx, &label, &rkey).await?;
-    tx.commit().await?;
+    match &positional.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [action @ ("apply" | "negate"), did, target] => {
+            let op = Op {
+                action: action.parse()?,
+                did: did.to_string(),
+                target: target.to_string(),
+                exp,
+            };
+            let op = tool.resolve(&mut db_conn, &op).await?;
+            let mut tx = db_conn.begin().await?;
+            tool.run(&mut tx, &op, &like_rkey).await?;
+            tx.commit().await?;
+        }
+        ["list", account] => {
+            let did = resolve_account(&mut db_conn, account).await?;
+            eprintln!("{}", handles::describe(&mut db_conn, &did).await);
+            for current in labels::current(&mut db_conn, &did).await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    current.val,
+                    current
+                        .exp
+                        .map(|exp| exp.to_rfc3339())
+                        .unwrap_or_else(|| "-".to_string()),
+                    current.like_rkey,
+                    current.seq
+                );
+            }
+        }
+        ["bulk", path] => {
+            let path = std::path::Path::new(path);
+            let ops = parse_bulk(path, &std::fs::read_to_string(path)?)?;
+            // Every row resolved (and the feed fetched) before anything is
+            // written, so the transaction below holds the labels lock only
+            // for the writes. Still all or nothing: a row that fails to
+            // write rolls back the ones before it.
+            let mut resolved = Vec::with_capacity(ops.len());
+            for op in &ops {
+                resolved.push(tool.resolve(&mut db_conn, op).await?);
+            }
+            let mut tx = db_conn.begin().await?;
+            for op in &resolved {
+                tool.run(&mut tx, op, &like_rkey).await?;
+            }
+            tx.commit().await?;
+            eprintln!("{} operation(s) done", ops.len());
+        }
+        ["audit"] => {
+            keys.push(tool.keypair.did());
+
+            const PAGE_SIZE: i64 = 1000;
+            let mut last_seq = 0;
+            let (mut rows, mut bad) = (0, 0);
+            loop {
+                let page = sqlx::query!(
+                    r#"
+                    SELECT seq, val, uri, neg, payload
+                    FROM labels
+                    WHERE seq > $1
+                    ORDER BY seq
+                    LIMIT $2
+                    "#,
+                    last_seq,
+                    PAGE_SIZE,
+                )
+                .fetch_all(&mut db_conn)
+                .await?;
+
+                for row in &page {
+                    let findings =
+                        labels::audit_payload(&row.val, &row.uri, row.neg, &row.payload, &keys);
+                    for finding in &findings {
+                        println!("{}\t{}\t{}\t{finding}", row.seq, row.uri, row.val);
+                    }
+                    bad += !findings.is_empty() as u64;
+                    last_seq = row.seq;
+                }
+                rows += page.len() as u64;
+                if (page.len() as i64) < PAGE_SIZE {
+                    break;
+                }
+            }
+
+            eprintln!("audited {rows} row(s), {bad} with problems");
+            if bad > 0 {
+                return Err(anyhow::anyhow!("{bad} row(s) failed the audit"));
+            }
+        }
+        // The original interface: a whole label as JSON on stdin.
+        ["emit", rkey] | [rkey] => {
+            let rkey = atrium_api::types::string::RecordKey::new(rkey.to_string())
+                .map_err(anyhow::Error::msg)?;
+            let label = serde_json::from_reader(std::io::stdin())?;
+            if dry_run {
+                return tool.print_signed(&label);
+            }
+            let mut tx = db_conn.begin().await?;
+            let seq = labels::emit(&tool.keypair, &mut tx, &label, &rkey).await?;
+            tx.commit().await?;
 
-    println!("{seq}");
+            match seq {
+                Some(seq) => println!("{seq}"),
+                None => eprintln!("label already in effect, nothing emitted"),
+            }
+        }
+        _ => return Err(anyhow::anyhow!(USAGE)),
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_csv_and_jsonl() {
+        let csv = parse_bulk(
+            std::path::Path::new("ops.csv"),
+            "action,did,target,exp\napply,did:plc:a,anthrocon-2025,\n\nnegate, did:plc:b ,mff-mmxxv\napply,did:plc:c,fwa-2026,2026-04-01T00:00:00Z\n",
+        )
+        .unwrap();
+        let jsonl = parse_bulk(
+            std::path::Path::new("ops.jsonl"),
+            r#"{"action": "apply", "did": "did:plc:a", "target": "anthrocon-2025"}
+{"action": "negate", "did": "did:plc:b", "target": "mff-mmxxv"}
+{"action": "apply", "did": "did:plc:c", "target": "fwa-2026", "exp": "2026-04-01T00:00:00Z"}
+"#,
+        )
+        .unwrap();
+        let expected = vec![
+            Op {
+                action: Action::Apply,
+                did: "did:plc:a".to_string(),
+                target: "anthrocon-2025".to_string(),
+                exp: None,
+            },
+            Op {
+                action: Action::Negate,
+                did: "did:plc:b".to_string(),
+                target: "mff-mmxxv".to_string(),
+                exp: None,
+            },
+            Op {
+                action: Action::Apply,
+                did: "did:plc:c".to_string(),
+                target: "fwa-2026".to_string(),
+                exp: Some("2026-04-01T00:00:00Z".parse().unwrap()),
+            },
+        ];
+        assert_eq!(csv, expected);
+        assert_eq!(jsonl, expected);
+    }
+
+    #[test]
+    fn bulk_reports_the_bad_line() {
+        let err = parse_bulk(
+            std::path::Path::new("ops.csv"),
+            "apply,did:plc:a,anthrocon-2025\nunlike,did:plc:a,anthrocon-2025\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().starts_with("ops.csv:2:"), "{err}");
+    }
+}
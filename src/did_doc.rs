@@ -0,0 +1,250 @@
+//! DID documents, for the two things we need from them: the labeler's
+//! `#atproto_label` verification method, i.e. the key clients check label
+//! signatures against, and a liker's `#atproto_pds` service, where its like
+//! records live. did:plc documents come from a PLC directory (or a local
+//! stand-in), did:web ones from the domain's `/.well-known/did.json`.
+
+pub const DEFAULT_PLC_URL: &str = "https://plc.directory";
+
+const LABEL_KEY_FRAGMENT: &str = "#atproto_label";
+const PDS_FRAGMENT: &str = "#atproto_pds";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("reqwest: {0}")]
+    Reqwest(#[from] reqwest::Error),
+
+    #[error("url: {0}")]
+    Url(#[from] url::ParseError),
+
+    #[error("unsupported DID method: {0}")]
+    UnsupportedMethod(String),
+
+    #[error("document is for {0}, not the DID asked for")]
+    WrongSubject(String),
+
+    #[error("no {LABEL_KEY_FRAGMENT} verification method with a publicKeyMultibase")]
+    NoLabelKey,
+
+    #[error("no {PDS_FRAGMENT} service")]
+    NoPds,
+
+    #[error("atrium_crypto: {0}")]
+    AtriumCrypto(#[from] atrium_crypto::Error),
+
+    #[error("the DID document publishes {published} as the label key, but we sign with {ours}")]
+    Mismatch { published: String, ours: String },
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Document {
+    id: String,
+    #[serde(default)]
+    verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    service: Vec<Service>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VerificationMethod {
+    id: String,
+    public_key_multibase: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Service {
+    id: String,
+    service_endpoint: String,
+}
+
+/// Whether `id` is `fragment` of `did`, written relative or absolute.
+fn is(id: &str, did: &atrium_api::types::string::Did, fragment: &str) -> bool {
+    id == fragment || id == format!("{}{fragment}", did.as_str())
+}
+
+#[derive(Clone)]
+pub struct Resolver {
+    pub reqwest_client: reqwest::Client,
+    pub plc_url: url::Url,
+}
+
+impl Resolver {
+    fn document_url(&self, did: &atrium_api::types::string::Did) -> Result<url::Url, Error> {
+        match did.method() {
+            "did:plc" => Ok(url::Url::parse(&format!(
+                "{}/{}",
+                self.plc_url.as_str().trim_end_matches('/'),
+                did.as_str()
+            ))?),
+            "did:web" => {
+                // A port is percent-encoded into the DID; paths are not allowed.
+                let host = did.as_str()["did:web:".len()..].replace("%3A", ":");
+                Ok(url::Url::parse(&format!(
+                    "https://{host}/.well-known/did.json"
+                ))?)
+            }
+            method => Err(Error::UnsupportedMethod(method.to_string())),
+        }
+    }
+
+    async fn document(&self, did: &atrium_api::types::string::Did) -> Result<Document, Error> {
+        let document = self
+            .reqwest_client
+            .get(self.document_url(did)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Document>()
+            .await?;
+        if document.id != did.as_str() {
+            return Err(Error::WrongSubject(document.id));
+        }
+        Ok(document)
+    }
+
+    /// The published label signing key, as a `did:key`: comparable with
+    /// `Keypair::did()` as a plain string.
+    pub async fn label_key(&self, did: &atrium_api::types::string::Did) -> Result<String, Error> {
+        let multibase = self
+            .document(did)
+            .await?
+            .verification_method
+            .into_iter()
+            .filter(|method| is(&method.id, did, LABEL_KEY_FRAGMENT))
+            .find_map(|method| method.public_key_multibase)
+            .ok_or(Error::NoLabelKey)?;
+
+        // Round-trip so equal keys compare equal however they were encoded.
+        let (alg, key) = atrium_crypto::did::parse_multikey(&multibase)?;
+        Ok(atrium_crypto::did::format_did_key(alg, &key)?)
+    }
+
+    /// Where `did`'s repo is hosted.
+    pub async fn pds(&self, did: &atrium_api::types::string::Did) -> Result<url::Url, Error> {
+        let endpoint = self
+            .document(did)
+            .await?
+            .service
+            .into_iter()
+            .find(|service| is(&service.id, did, PDS_FRAGMENT))
+            .ok_or(Error::NoPds)?
+            .service_endpoint;
+        Ok(url::Url::parse(&endpoint)?)
+    }
+
+    /// Check that labels signed with `keypair` will verify for clients
+    /// resolving `did`.
+    pub async fn verify_label_key(
+        &self,
+        did: &atrium_api::types::string::Did,
+        keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    ) -> Result<(), Error> {
+        use atrium_crypto::keypair::Did as _;
+
+        let published = self.label_key(did).await?;
+        let ours = keypair.did();
+        if published != ours {
+            return Err(Error::Mismatch { published, ours });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_crypto::keypair::Did as _;
+
+    /// A PLC directory that knows one document, for `did` with `label_key`
+    /// (a `did:key`) as its label key.
+    async fn serve_plc(did: &str, label_key: &str) -> url::Url {
+        let document = serde_json::json!({
+            "@context": ["https://www.w3.org/ns/did/v1"],
+            "id": did,
+            "verificationMethod": [
+                {
+                    "id": format!("{did}#atproto"),
+                    "type": "Multikey",
+                    "controller": did,
+                    "publicKeyMultibase": "zQ3shokFTS3brHcDQrn82RUDfCZESWL1ZdCEJwekUDPQiYBme",
+                },
+                {
+                    "id": format!("{did}#atproto_label"),
+                    "type": "Multikey",
+                    "controller": did,
+                    "publicKeyMultibase": label_key.strip_prefix("did:key:").unwrap(),
+                },
+            ],
+            "service": [
+                {
+                    "id": "#atproto_pds",
+                    "type": "AtprotoPersonalDataServer",
+                    "serviceEndpoint": "https://pds.example",
+                },
+            ],
+        });
+        let app = axum::Router::new().route(
+            &format!("/{did}"),
+            axum::routing::get(move || async move { axum::Json(document) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url::Url::parse(&format!("http://{addr}/")).unwrap()
+    }
+
+    fn resolver(plc_url: url::Url) -> Resolver {
+        Resolver {
+            reqwest_client: reqwest::Client::new(),
+            plc_url,
+        }
+    }
+
+    #[test]
+    fn did_web_document_url() {
+        let resolver = resolver(url::Url::parse(DEFAULT_PLC_URL).unwrap());
+        let did = atrium_api::types::string::Did::new("did:web:labeler.example%3A8443".to_string())
+            .unwrap();
+        assert_eq!(
+            resolver.document_url(&did).unwrap().as_str(),
+            "https://labeler.example:8443/.well-known/did.json"
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_the_label_key() {
+        let did = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let keypair = crate::test_db::keypair();
+        let plc_url = serve_plc(did.as_str(), &keypair.did()).await;
+
+        assert_eq!(
+            resolver(plc_url.clone()).label_key(&did).await.unwrap(),
+            keypair.did()
+        );
+        assert_eq!(
+            resolver(plc_url.clone()).pds(&did).await.unwrap().as_str(),
+            "https://pds.example/"
+        );
+
+        resolver(plc_url.clone())
+            .verify_label_key(&did, &keypair)
+            .await
+            .unwrap();
+        let other = atrium_crypto::keypair::Secp256k1Keypair::import(&[2; 32]).unwrap();
+        assert!(matches!(
+            resolver(plc_url.clone())
+                .verify_label_key(&did, &other)
+                .await,
+            Err(Error::Mismatch { .. })
+        ));
+
+        let stranger = atrium_api::types::string::Did::new("did:plc:stranger".to_string()).unwrap();
+        assert!(matches!(
+            resolver(plc_url).label_key(&stranger).await,
+            Err(Error::Reqwest(_))
+        ));
+    }
+}
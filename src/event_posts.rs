@@ -0,0 +1,260 @@
+//! The post each event gets, which likers like to get its label.
+//!
+//! `plain` is just the event's name, linked to its page on `ui_endpoint`.
+//! `rich` adds the dates and venue (so two editions sharing a name stay
+//! apart in a feed), a `#tag` for the event's series, and a link card to the
+//! event page.
+//!
+//! A post that no longer matches its event (renamed, new dates, another
+//! template) is updated in place, keeping its rkey and so its likes, when
+//! `post_updates` is `apply`; `dry_run` only reports it.
+
+/// Which post `sync_labels` creates for a new event (`post_template`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Template {
+    Plain,
+    Rich,
+}
+
+/// What `sync_labels` does with posts that no longer match their event
+/// (`post_updates`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Updates {
+    Off,
+    DryRun,
+    Apply,
+}
+
+/// Post text with its facets. Facets index UTF-8 bytes, which `String::len`
+/// counts, so offsets stay right for names that aren't ASCII.
+#[derive(Default)]
+struct RichText {
+    text: String,
+    facets: Vec<atrium_api::app::bsky::richtext::facet::Main>,
+}
+
+impl RichText {
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+    }
+
+    fn push_with(
+        &mut self,
+        s: &str,
+        feature: atrium_api::app::bsky::richtext::facet::MainFeaturesItem,
+    ) {
+        let byte_start = self.text.len();
+        self.text.push_str(s);
+        self.facets.push(
+            atrium_api::app::bsky::richtext::facet::MainData {
+                features: vec![atrium_api::types::Union::Refs(feature)],
+                index: atrium_api::app::bsky::richtext::facet::ByteSliceData {
+                    byte_start,
+                    byte_end: self.text.len(),
+                }
+                .into(),
+            }
+            .into(),
+        );
+    }
+
+    fn push_link(&mut self, s: &str, uri: &str) {
+        self.push_with(
+            s,
+            atrium_api::app::bsky::richtext::facet::MainFeaturesItem::Link(Box::new(
+                atrium_api::app::bsky::richtext::facet::LinkData {
+                    uri: uri.to_string(),
+                }
+                .into(),
+            )),
+        );
+    }
+
+    fn push_tag(&mut self, tag: &str) {
+        self.push_with(
+            &format!("#{tag}"),
+            atrium_api::app::bsky::richtext::facet::MainFeaturesItem::Tag(Box::new(
+                atrium_api::app::bsky::richtext::facet::TagData {
+                    tag: tag.to_string(),
+                }
+                .into(),
+            )),
+        );
+    }
+}
+
+/// The hashtag for a series: its id without punctuation, e.g. `#anthrocon`
+/// for `anthrocon` and `#mff` for `m-f-f`. `None` if nothing is left.
+pub fn series_tag(series_id: &str) -> Option<String> {
+    let tag = series_id
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>();
+    (!tag.is_empty()).then_some(tag)
+}
+
+/// The post for `event`, created at `created_at`.
+pub fn record(
+    template: Template,
+    ui_endpoint: &str,
+    event: &crate::events::IngestedEvent,
+    created_at: chrono::DateTime<chrono::Utc>,
+) -> atrium_api::app::bsky::feed::post::Record {
+    let uri = format!("{}/{}", ui_endpoint, event.id);
+    let mut rich_text = RichText::default();
+    rich_text.push_link(&event.name, &uri);
+
+    let mut embed = None;
+    if template == Template::Rich {
+        let dates = crate::label_strings::format_dates("en", event.start_date, event.end_date);
+        rich_text.push(&format!("\n📅 {dates}\n📍 {}", event.venue));
+        if let Some(tag) = event.series_id.as_deref().and_then(series_tag) {
+            rich_text.push("\n");
+            rich_text.push_tag(&tag);
+        }
+
+        embed = Some(atrium_api::types::Union::Refs(
+            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedExternalMain(Box::new(
+                atrium_api::app::bsky::embed::external::MainData {
+                    external: atrium_api::app::bsky::embed::external::ExternalData {
+                        description: format!("{dates} · {}", event.venue),
+                        thumb: None,
+                        title: event.name.clone(),
+                        uri,
+                    }
+                    .into(),
+                }
+                .into(),
+            )),
+        ));
+    }
+
+    atrium_api::app::bsky::feed::post::RecordData {
+        created_at: atrium_api::types::string::Datetime::new(created_at.fixed_offset()),
+        embed,
+        entities: None,
+        labels: None,
+        langs: Some(vec![atrium_api::types::string::Language::new(
+            "en".to_string(),
+        )
+        .unwrap()]),
+        reply: None,
+        tags: None,
+        facets: Some(rich_text.facets),
+        text: rich_text.text,
+    }
+    .into()
+}
+
+/// What differs between the post in the repo and the one `record` would
+/// make now, e.g. `["text", "embed"]`; empty if it is up to date. The
+/// creation time doesn't count.
+pub fn changes(
+    existing: &atrium_api::app::bsky::feed::post::RecordData,
+    wanted: &atrium_api::app::bsky::feed::post::RecordData,
+) -> Vec<&'static str> {
+    fn same<T: serde::Serialize>(a: &T, b: &T) -> bool {
+        serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+    }
+    [
+        ("text", existing.text == wanted.text),
+        ("facets", same(&existing.facets, &wanted.facets)),
+        ("embed", same(&existing.embed, &wanted.embed)),
+        ("langs", same(&existing.langs, &wanted.langs)),
+    ]
+    .into_iter()
+    .filter(|(_, same)| !same)
+    .map(|(field, _)| field)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rich_post_offsets_and_card() {
+        let event: crate::events::IngestedEvent = serde_json::from_str(
+            r#"{"id":"jmof-2025","name":"ジャパンミーティングオブファーリーズ","venue":"Hotel Ōsaka","locale":"ja","startDate":"2025-02-07","endDate":"2025-02-09","seriesId":"j-mo-f"}"#,
+        )
+        .unwrap();
+        let created_at = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let plain = record(Template::Plain, "https://cons.fyi", &event, created_at);
+        assert_eq!(plain.text, event.name);
+        assert!(plain.embed.is_none());
+        let facets = plain.facets.as_ref().unwrap();
+        assert_eq!(facets.len(), 1);
+        assert_eq!(facets[0].index.byte_end, event.name.len());
+
+        let rich = record(Template::Rich, "https://cons.fyi", &event, created_at);
+        assert_eq!(
+            rich.text,
+            "ジャパンミーティングオブファーリーズ\n📅 February 7–9, 2025\n📍 Hotel Ōsaka\n#jmof"
+        );
+        let facets = rich.facets.as_ref().unwrap();
+        let slices = facets
+            .iter()
+            .map(|facet| &rich.text.as_bytes()[facet.index.byte_start..facet.index.byte_end])
+            .collect::<Vec<_>>();
+        assert_eq!(slices, vec![event.name.as_bytes(), "#jmof".as_bytes()]);
+        let atrium_api::types::Union::Refs(
+            atrium_api::app::bsky::richtext::facet::MainFeaturesItem::Tag(tag),
+        ) = &facets[1].features[0]
+        else {
+            panic!("not a tag: {:?}", facets[1].features);
+        };
+        assert_eq!(tag.tag, "jmof");
+
+        let Some(atrium_api::types::Union::Refs(
+            atrium_api::app::bsky::feed::post::RecordEmbedRefs::AppBskyEmbedExternalMain(card),
+        )) = &rich.embed
+        else {
+            panic!("no link card: {:?}", rich.embed);
+        };
+        assert_eq!(card.external.uri, "https://cons.fyi/jmof-2025");
+        assert_eq!(
+            card.external.description,
+            "February 7–9, 2025 · Hotel Ōsaka"
+        );
+
+        assert_eq!(series_tag("--"), None);
+    }
+
+    #[test]
+    fn changes_ignore_created_at() {
+        let mut event: crate::events::IngestedEvent = serde_json::from_str(
+            r#"{"id":"ac-2025","name":"Anthrocon 2025","venue":"DLCC","locale":"en","startDate":"2025-07-03","endDate":"2025-07-06"}"#,
+        )
+        .unwrap();
+        let t = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        let existing = record(Template::Plain, "https://cons.fyi", &event, t);
+
+        let later = record(
+            Template::Plain,
+            "https://cons.fyi",
+            &event,
+            t + chrono::Days::new(1),
+        );
+        assert!(changes(&existing, &later).is_empty());
+
+        assert_eq!(
+            changes(
+                &existing,
+                &record(Template::Rich, "https://cons.fyi", &event, t)
+            ),
+            vec!["text", "embed"]
+        );
+
+        event.id = "anthrocon-2025".to_string();
+        assert_eq!(
+            changes(
+                &existing,
+                &record(Template::Plain, "https://cons.fyi", &event, t)
+            ),
+            vec!["facets"]
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! The events feed (`current.jsonl`) and how an event becomes a label.
+
+/// How long after an event ends its label stays on.
+pub const EXPIRY_DATE_GRACE_PERIOD: chrono::Days = chrono::Days::new(7);
+
+pub fn id_to_label(s: &str) -> String {
+    static NUMBERS_RE: std::sync::LazyLock<regex::Regex> =
+        std::sync::LazyLock::new(|| regex::Regex::new(r"\d+").unwrap());
+    NUMBERS_RE
+        .replace_all(&s.to_ascii_lowercase(), |caps: &regex::Captures| {
+            crate::roman::to_roman(caps[0].parse().unwrap()).to_ascii_lowercase()
+        })
+        .to_string()
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlueskyRef {
+    pub did: String,
+    pub handle: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestedEvent {
+    pub id: String,
+    pub name: String,
+    pub venue: String,
+    pub locale: String,
+    pub address: Option<String>,
+    pub start_date: chrono::NaiveDate,
+    pub end_date: chrono::NaiveDate,
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub series_id: Option<String>,
+    #[serde(default)]
+    pub bluesky: Option<BlueskyRef>,
+    #[serde(default)]
+    pub key_dates: Option<serde_json::Value>,
+    /// Ids this event was published under before, newest last. A rename
+    /// keeps the event's post and moves its likers to the new label.
+    #[serde(default)]
+    pub previous_ids: Vec<String>,
+    /// How clients should treat the event's label; see src/label_policy.rs.
+    #[serde(default)]
+    pub label_policy: Option<crate::label_policy::Policy>,
+    /// The event's name (and optionally description) in other languages, by
+    /// BCP 47 tag; see src/label_strings.rs.
+    #[serde(default)]
+    pub localizations: std::collections::BTreeMap<String, Localized>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Localized {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct AssociatedEvent {
+    pub event: IngestedEvent,
+    pub rkey: Option<atrium_api::types::string::RecordKey>,
+    pub label_id: String,
+}
+
+impl IngestedEvent {
+    pub fn end_time(&self) -> chrono::DateTime<chrono::Utc> {
+        let date = self.end_date + chrono::Days::new(1);
+        let timezone = self
+            .timezone
+            .as_ref()
+            .and_then(|tz| tz.parse().ok())
+            .unwrap_or(chrono_tz::UTC);
+
+        date.and_time(chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap())
+            .and_local_timezone(timezone)
+            .earliest()
+            .or_else(|| {
+                self.end_date
+                    .and_time(chrono::NaiveTime::from_hms_opt(1, 0, 0).unwrap())
+                    .and_local_timezone(timezone)
+                    .earliest()
+            })
+            .unwrap()
+            .to_utc()
+    }
+}
+
+pub async fn fetch_events(
+    reqwest_client: &reqwest::Client,
+    events_url: &str,
+) -> Result<std::collections::HashMap<String, AssociatedEvent>, anyhow::Error> {
+    reqwest_client
+        .get(events_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?
+        .lines()
+        .map(|line| {
+            let event = serde_json::from_str::<IngestedEvent>(line)?;
+            Ok::<_, anyhow::Error>((
+                event.id.clone(),
+                AssociatedEvent {
+                    rkey: None,
+                    label_id: id_to_label(&event.id),
+                    event,
+                },
+            ))
+        })
+        .collect::<Result<_, _>>()
+}
@@ -0,0 +1,132 @@
+//! DID → handle cache, fed from Jetstream identity events so operators can
+//! find a labeled account by handle without resolving it over the network.
+//! Best effort: a DID we haven't seen an identity event for since we first
+//! labeled it has no handle.
+
+/// Record `did`'s handle as of `time`, if `did` has ever been labeled:
+/// identity events come for the whole network, and only labeled accounts
+/// are looked up. An event older than what is cached (a replay) changes
+/// nothing; `None` means the handle no longer verifies.
+pub async fn record(
+    db_conn: &mut sqlx::PgConnection,
+    did: &str,
+    handle: Option<&str>,
+    time: chrono::DateTime<chrono::Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO handles (did, handle, updated_at)
+        SELECT $1, $2, $3
+        WHERE EXISTS (SELECT 1 FROM current_labels WHERE uri = $1)
+        ON CONFLICT (did) DO UPDATE
+        SET handle = excluded.handle, updated_at = excluded.updated_at
+        WHERE handles.updated_at <= excluded.updated_at
+        "#,
+        did,
+        handle,
+        time,
+    )
+    .execute(db_conn)
+    .await?;
+    Ok(())
+}
+
+pub async fn handle(
+    db_conn: &mut sqlx::PgConnection,
+    did: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    Ok(
+        sqlx::query_scalar!(r#"SELECT handle FROM handles WHERE did = $1"#, did)
+            .fetch_optional(db_conn)
+            .await?
+            .flatten(),
+    )
+}
+
+/// The DID currently holding `handle`, if any. Handles are compared
+/// case-insensitively.
+pub async fn did(
+    db_conn: &mut sqlx::PgConnection,
+    handle: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT did FROM handles
+        WHERE lower(handle) = lower($1)
+        ORDER BY updated_at DESC
+        LIMIT 1
+        "#,
+        handle.strip_prefix('@').unwrap_or(handle)
+    )
+    .fetch_optional(db_conn)
+    .await
+}
+
+/// `did` for logs: `did:plc:… (@alice.bsky.social)` when the handle is known.
+pub async fn describe(db_conn: &mut sqlx::PgConnection, did: &str) -> String {
+    match handle(db_conn, did).await {
+        Ok(Some(handle)) => format!("{did} (@{handle})"),
+        _ => did.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn latest_identity_wins() {
+        let db_pool = crate::test_db::pool().await;
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let t = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+        sqlx::query(
+            r#"
+            INSERT INTO current_labels (uri, val, neg, exp, like_rkey, cts, seq)
+            VALUES ('did:plc:a', 'examplecon-mmxxv', false, NULL, '3kabc', NOW(), 1)
+            "#,
+        )
+        .execute(&mut *db_conn)
+        .await
+        .unwrap();
+
+        record(&mut db_conn, "did:plc:a", Some("alice.bsky.social"), t)
+            .await
+            .unwrap();
+        record(
+            &mut db_conn,
+            "did:plc:a",
+            Some("alice.example.com"),
+            t + chrono::Duration::seconds(60),
+        )
+        .await
+        .unwrap();
+        // replayed
+        record(&mut db_conn, "did:plc:a", Some("alice.bsky.social"), t)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            handle(&mut db_conn, "did:plc:a").await.unwrap().as_deref(),
+            Some("alice.example.com")
+        );
+        assert_eq!(
+            did(&mut db_conn, "@Alice.Example.com")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("did:plc:a")
+        );
+        assert_eq!(did(&mut db_conn, "alice.bsky.social").await.unwrap(), None);
+        assert_eq!(
+            describe(&mut db_conn, "did:plc:a").await,
+            "did:plc:a (@alice.example.com)"
+        );
+        // never labeled
+        record(&mut db_conn, "did:plc:b", Some("bob.bsky.social"), t)
+            .await
+            .unwrap();
+        assert_eq!(describe(&mut db_conn, "did:plc:b").await, "did:plc:b");
+    }
+}
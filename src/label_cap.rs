@@ -0,0 +1,445 @@
+//! Per-user cap on simultaneous con labels: clients only show a few labels
+//! per account, so past `max` we choose which ones are on.
+//!
+//! Eviction policy: labels for events that have already ended (but are still
+//! inside `EXPIRY_DATE_GRACE_PERIOD`) go first and are simply negated. Beyond
+//! that the soonest-ending events win; a like that loses out, whether the new
+//! one or one already on, is recorded in `deferred_labels` and applied by
+//! `promote` once a slot frees up (an older label expires, or is unliked).
+//!
+//! Series labels (see `like_labels`) and labels applied by hand are outside
+//! the cap: they neither count nor get evicted, so no like can push out what a
+//! moderator put on.
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Capped {
+    /// Seq of the label emitted for the new like, if it was.
+    pub applied: Option<i64>,
+    /// Whether the new like was deferred instead.
+    pub deferred: bool,
+    /// Vals negated to make room: ended events, and later-ending ones that
+    /// were deferred in turn.
+    pub evicted: Vec<String>,
+}
+
+/// Whether the event behind a label with `exp` has ended.
+fn ended(exp: Option<chrono::DateTime<chrono::Utc>>, now: chrono::DateTime<chrono::Utc>) -> bool {
+    exp.is_some_and(|exp| exp - crate::events::EXPIRY_DATE_GRACE_PERIOD <= now)
+}
+
+/// Sort key: upcoming before ended, then soonest-ending first. No expiry
+/// sorts after every dated upcoming event.
+fn rank(
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> (bool, chrono::DateTime<chrono::Utc>) {
+    (
+        ended(exp, now),
+        exp.unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC),
+    )
+}
+
+/// `uri`'s current labels that count towards the cap.
+async fn counted(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+) -> Result<Vec<crate::labels::CurrentLabel>, sqlx::Error> {
+    let mut current = crate::labels::current(tx, uri).await?;
+    current.retain(|current| {
+        !crate::like_labels::is_series(&current.val)
+            && current.like_rkey != crate::labels::ADMIN_LIKE_RKEY
+    });
+    Ok(current)
+}
+
+async fn defer(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+    val: &str,
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+    like_rkey: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO deferred_labels (uri, val, exp, like_rkey)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (uri, val) DO UPDATE
+        SET exp = excluded.exp, like_rkey = excluded.like_rkey
+        "#,
+        uri,
+        val,
+        exp,
+        like_rkey,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Emit `label` (a positive label for a like) unless that would put its
+/// subject over `max` labels, evicting or deferring per the policy above.
+pub async fn emit(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    label: &atrium_api::com::atproto::label::defs::Label,
+    like_rkey: &str,
+    max: usize,
+) -> Result<Capped, crate::labels::EmitError> {
+    let now = chrono::Utc::now();
+    let exp = label.exp.as_ref().map(|exp| exp.as_ref().to_utc());
+    let current = counted(tx, &label.uri).await?;
+
+    // Already on (a replay, or a second like of the same event): no change
+    // in the count.
+    if crate::like_labels::is_series(&label.val)
+        || current.len() < max
+        || current.iter().any(|current| current.val == label.val)
+    {
+        return Ok(Capped {
+            applied: crate::labels::emit(keypair, tx, label, like_rkey).await?,
+            ..Default::default()
+        });
+    }
+
+    let mut candidates = current.iter().map(Some).chain([None]).collect::<Vec<_>>();
+    candidates.sort_by_key(|candidate| rank(candidate.map_or(exp, |current| current.exp), now));
+
+    let mut capped = Capped::default();
+    for (i, candidate) in candidates.iter().enumerate() {
+        let keep = i < max;
+        match candidate {
+            None if keep => {}
+            None => {
+                defer(tx, &label.uri, &label.val, exp, like_rkey).await?;
+                capped.deferred = true;
+            }
+            Some(_) if keep => {}
+            Some(current) => {
+                if !ended(current.exp, now) {
+                    defer(
+                        tx,
+                        &current.uri,
+                        &current.val,
+                        current.exp,
+                        &current.like_rkey,
+                    )
+                    .await?;
+                }
+                crate::labels::negate(keypair, tx, &label.src, current).await?;
+                capped.evicted.push(current.val.clone());
+            }
+        }
+    }
+    if !capped.deferred {
+        capped.applied = crate::labels::emit(keypair, tx, label, like_rkey).await?;
+    }
+    Ok(capped)
+}
+
+/// Apply `uri`'s deferred labels, soonest-ending first, while it has fewer
+/// than `max` on, and drop the ones that expired while waiting. Nothing is
+/// promoted for an account whose labels are set aside by `accounts::suspend`.
+/// Returns the vals applied.
+pub async fn promote(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    uri: &str,
+    max: usize,
+) -> Result<Vec<String>, crate::labels::EmitError> {
+    sqlx::query!(
+        r#"
+        DELETE FROM deferred_labels
+        WHERE uri = $1 AND exp <= CURRENT_TIMESTAMP
+        "#,
+        uri
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    let suspended = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM suspended_labels WHERE uri = $1) AS "suspended!""#,
+        uri
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    if suspended {
+        return Ok(vec![]);
+    }
+
+    let now = chrono::Utc::now();
+    let room = max.saturating_sub(counted(tx, uri).await?.len());
+    let waiting = sqlx::query!(
+        r#"
+        SELECT val, exp, like_rkey
+        FROM deferred_labels
+        WHERE uri = $1
+        ORDER BY exp NULLS LAST, val
+        "#,
+        uri
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let mut promoted = vec![];
+    for row in waiting
+        .into_iter()
+        .filter(|row| !ended(row.exp, now))
+        .take(room)
+    {
+        sqlx::query!(
+            r#"DELETE FROM deferred_labels WHERE uri = $1 AND val = $2"#,
+            uri,
+            row.val
+        )
+        .execute(&mut **tx)
+        .await?;
+        crate::labels::apply(keypair, tx, src, uri, &row.val, row.exp, &row.like_rkey).await?;
+        promoted.push(row.val);
+    }
+    Ok(promoted)
+}
+
+/// Forget a deferred like that was unliked. Returns whether there was one.
+pub async fn forget(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+    like_rkey: &str,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"DELETE FROM deferred_labels WHERE uri = $1 AND like_rkey = $2"#,
+        uri,
+        like_rkey,
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+/// Follow an event rename or date change (`Some(new val, new exp)`) or
+/// removal (`None`) in the deferred likes, as `sync_labels` does for the
+/// labels that are on.
+pub async fn retarget(
+    tx: &mut sqlx::PgTransaction<'_>,
+    old_val: &str,
+    new: Option<(&str, chrono::DateTime<chrono::Utc>)>,
+) -> Result<u64, sqlx::Error> {
+    let result = match new {
+        Some((val, exp)) => {
+            sqlx::query!(
+                r#"
+                UPDATE deferred_labels SET val = $2, exp = $3
+                WHERE val = $1
+                AND ($1 = $2 OR NOT EXISTS (
+                    SELECT 1 FROM deferred_labels d
+                    WHERE d.uri = deferred_labels.uri AND d.val = $2
+                ))
+                "#,
+                old_val,
+                val,
+                exp,
+            )
+            .execute(&mut **tx)
+            .await?
+        }
+        None => {
+            sqlx::query!(r#"DELETE FROM deferred_labels WHERE val = $1"#, old_val)
+                .execute(&mut **tx)
+                .await?
+        }
+    };
+    Ok(result.rows_affected())
+}
+
+/// Every `every`, give each account with deferred likes a chance at the
+/// slots its expired labels left. A failed pass is logged and retried on the
+/// next tick.
+pub async fn periodic_promote(
+    db_pool: &sqlx::PgPool,
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    src: &atrium_api::types::string::Did,
+    max: usize,
+    every: std::time::Duration,
+) {
+    let mut interval = tokio::time::interval(every);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        if let Err(e) = promote_all(db_pool, keypair, src, max).await {
+            log::error!("failed to apply deferred labels: {e}");
+        }
+    }
+}
+
+/// One `periodic_promote` pass over every account with deferred likes.
+async fn promote_all(
+    db_pool: &sqlx::PgPool,
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    src: &atrium_api::types::string::Did,
+    max: usize,
+) -> Result<(), crate::labels::EmitError> {
+    let uris = sqlx::query_scalar!(r#"SELECT DISTINCT uri FROM deferred_labels"#)
+        .fetch_all(db_pool)
+        .await?;
+    for uri in uris {
+        let mut tx = db_pool.begin().await?;
+        let promoted = promote(keypair, &mut tx, src, &uri, max).await?;
+        tx.commit().await?;
+        if !promoted.is_empty() {
+            log::info!("{uri}: applied deferred label(s) {promoted:?}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn like(
+        src: &atrium_api::types::string::Did,
+        val: &str,
+        exp: chrono::DateTime<chrono::Utc>,
+    ) -> atrium_api::com::atproto::label::defs::Label {
+        crate::labels::new_label(src, "did:plc:a", val, false, Some(exp))
+    }
+
+    async fn on(db_pool: &sqlx::PgPool) -> Vec<String> {
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let mut vals = crate::labels::current(&mut db_conn, "did:plc:a")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|current| current.val)
+            .collect::<Vec<_>>();
+        vals.sort();
+        vals
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn cap_evicts_defers_and_promotes() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let now = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0);
+        let days = |n| now + chrono::Duration::days(n);
+        // Ended yesterday, still in its grace period.
+        let ended = now + crate::events::EXPIRY_DATE_GRACE_PERIOD - chrono::Duration::days(1);
+
+        let mut tx = db_pool.begin().await.unwrap();
+        for (val, exp) in [("past", ended), ("june", days(60))] {
+            emit(&keypair, &mut tx, &like(&src, val, exp), "3k", 2)
+                .await
+                .unwrap();
+        }
+        // The ended event makes room.
+        let capped = emit(&keypair, &mut tx, &like(&src, "may", days(30)), "3kmay", 2)
+            .await
+            .unwrap();
+        assert!(capped.applied.is_some());
+        assert_eq!(capped.evicted, vec!["past"]);
+        // Sooner than june, which is deferred in its place.
+        let capped = emit(
+            &keypair,
+            &mut tx,
+            &like(&src, "april", days(10)),
+            "3kapr",
+            2,
+        )
+        .await
+        .unwrap();
+        assert_eq!(capped.evicted, vec!["june"]);
+        // Later than both: deferred itself.
+        let capped = emit(&keypair, &mut tx, &like(&src, "july", days(90)), "3kjul", 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            capped,
+            Capped {
+                applied: None,
+                deferred: true,
+                evicted: vec![],
+            }
+        );
+        tx.commit().await.unwrap();
+        assert_eq!(on(&db_pool).await, vec!["april", "may"]);
+
+        // Unlike of a deferred like, then of a label that is on.
+        let mut tx = db_pool.begin().await.unwrap();
+        assert!(forget(&mut tx, "did:plc:a", "3kjul").await.unwrap());
+        let may = crate::labels::current(&mut tx, "did:plc:a")
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|current| current.val == "may")
+            .unwrap();
+        crate::labels::negate(&keypair, &mut tx, &src, &may)
+            .await
+            .unwrap();
+        assert_eq!(
+            promote(&keypair, &mut tx, &src, "did:plc:a", 2)
+                .await
+                .unwrap(),
+            vec!["june"]
+        );
+        assert!(promote(&keypair, &mut tx, &src, "did:plc:a", 2)
+            .await
+            .unwrap()
+            .is_empty());
+        tx.commit().await.unwrap();
+        assert_eq!(on(&db_pool).await, vec!["april", "june"]);
+    }
+
+    // A label put on by hand takes no slot, and no like evicts it.
+    #[tokio::test]
+    #[ignore]
+    async fn admin_labels_are_outside_the_cap() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let now = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0);
+        let days = |n| now + chrono::Duration::days(n);
+
+        let mut tx = db_pool.begin().await.unwrap();
+        crate::labels::apply(
+            &keypair,
+            &mut tx,
+            &src,
+            "did:plc:a",
+            "staff",
+            Some(days(90)),
+            crate::labels::ADMIN_LIKE_RKEY,
+        )
+        .await
+        .unwrap();
+        for (val, exp, like_rkey) in [("may", days(30), "3kmay"), ("april", days(10), "3kapr")] {
+            let capped = emit(&keypair, &mut tx, &like(&src, val, exp), like_rkey, 2)
+                .await
+                .unwrap();
+            assert!(capped.applied.is_some(), "{val}");
+            assert!(capped.evicted.is_empty(), "{val}");
+        }
+        // At the cap now: the later like is deferred rather than the
+        // moderator's label evicted.
+        let capped = emit(&keypair, &mut tx, &like(&src, "june", days(60)), "3kjun", 2)
+            .await
+            .unwrap();
+        assert!(capped.deferred);
+        assert!(capped.evicted.is_empty());
+        tx.commit().await.unwrap();
+        assert_eq!(on(&db_pool).await, vec!["april", "may", "staff"]);
+    }
+
+    #[test]
+    fn ended_sorts_last() {
+        let now = chrono::Utc::now();
+        let days = |n| Some(now + chrono::Duration::days(n));
+        // Within the 7-day grace period of its expiry: ended.
+        let mut exps = vec![None, days(100), days(3), days(10)];
+        exps.sort_by_key(|exp| rank(*exp, now));
+        assert_eq!(exps, vec![days(10), days(100), None, days(3)]);
+    }
+}
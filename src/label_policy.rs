@@ -0,0 +1,253 @@
+//! How clients treat a con label (`blurs`, `severity`, `defaultSetting`,
+//! `adultOnly` on its definition): `inform`, no blur, shown by default, unless
+//! the events feed (`labelPolicy`) or the local override file says otherwise.
+//!
+//! Later wins: the defaults, the feed's policy for the event, the override
+//! file's `[series.<series id>]`, then its `[events.<event id>]`. A series
+//! label takes its latest edition's feed policy and the series override.
+//! Values outside what atproto allows fail to parse, so a bad feed line or
+//! override file fails the sync and the published definitions stay as they
+//! were.
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("config: {0}")]
+    Config(#[from] config::ConfigError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Blurs {
+    Content,
+    Media,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Inform,
+    Alert,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DefaultSetting {
+    Ignore,
+    Warn,
+    Hide,
+}
+
+impl Blurs {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Blurs::Content => "content",
+            Blurs::Media => "media",
+            Blurs::None => "none",
+        }
+    }
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Inform => "inform",
+            Severity::Alert => "alert",
+            Severity::None => "none",
+        }
+    }
+}
+
+impl DefaultSetting {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DefaultSetting::Ignore => "ignore",
+            DefaultSetting::Warn => "warn",
+            DefaultSetting::Hide => "hide",
+        }
+    }
+}
+
+/// Any of a definition's policy fields; unset ones are left to what comes
+/// before. camelCase in the feed, snake_case in the override file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Policy {
+    pub blurs: Option<Blurs>,
+    pub severity: Option<Severity>,
+    #[serde(alias = "default_setting")]
+    pub default_setting: Option<DefaultSetting>,
+    #[serde(alias = "adult_only")]
+    pub adult_only: Option<bool>,
+}
+
+impl Policy {
+    /// `self`, with `over`'s set fields on top.
+    fn then(&self, over: Option<&Policy>) -> Policy {
+        let Some(over) = over else {
+            return self.clone();
+        };
+        Policy {
+            blurs: over.blurs.or(self.blurs),
+            severity: over.severity.or(self.severity),
+            default_setting: over.default_setting.or(self.default_setting),
+            adult_only: over.adult_only.or(self.adult_only),
+        }
+    }
+
+    /// A label definition under this policy, defaults for what is unset.
+    pub fn definition(
+        &self,
+        identifier: String,
+        locales: Vec<atrium_api::com::atproto::label::defs::LabelValueDefinitionStrings>,
+    ) -> atrium_api::com::atproto::label::defs::LabelValueDefinitionData {
+        atrium_api::com::atproto::label::defs::LabelValueDefinitionData {
+            adult_only: Some(self.adult_only.unwrap_or(false)),
+            blurs: self.blurs.unwrap_or(Blurs::None).as_str().to_string(),
+            default_setting: Some(
+                self.default_setting
+                    .unwrap_or(DefaultSetting::Warn)
+                    .as_str()
+                    .to_string(),
+            ),
+            identifier,
+            locales,
+            severity: self
+                .severity
+                .unwrap_or(Severity::Inform)
+                .as_str()
+                .to_string(),
+        }
+    }
+}
+
+/// The local override file (`label_policies_path`), e.g.:
+///
+/// ```toml
+/// [series.anthrocon]
+/// default_setting = "hide"
+///
+/// [events.some-18plus-con-2025]
+/// adult_only = true
+/// ```
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    #[serde(default)]
+    pub events: std::collections::HashMap<String, Policy>,
+    #[serde(default)]
+    pub series: std::collections::HashMap<String, Policy>,
+}
+
+impl Overrides {
+    /// Read the override file; any format the `config` crate knows, by
+    /// extension.
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        Ok(config::Config::builder()
+            .add_source(config::File::from(path))
+            .build()?
+            .try_deserialize()?)
+    }
+
+    pub fn for_event(&self, event: &crate::events::IngestedEvent) -> Policy {
+        Policy::default()
+            .then(event.label_policy.as_ref())
+            .then(
+                event
+                    .series_id
+                    .as_ref()
+                    .and_then(|series_id| self.series.get(series_id)),
+            )
+            .then(self.events.get(&event.id))
+    }
+
+    pub fn for_series(&self, series_id: &str, latest: &crate::events::IngestedEvent) -> Policy {
+        Policy::default()
+            .then(latest.label_policy.as_ref())
+            .then(self.series.get(series_id))
+    }
+
+    /// Override entries naming no event or series in the feed, most likely
+    /// typos.
+    pub fn unmatched<'a>(
+        &'a self,
+        events: impl Iterator<Item = &'a crate::events::IngestedEvent> + Clone,
+    ) -> Vec<&'a str> {
+        let mut unmatched = self
+            .events
+            .keys()
+            .filter(|id| !events.clone().any(|event| event.id == **id))
+            .chain(self.series.keys().filter(|series_id| {
+                !events
+                    .clone()
+                    .any(|event| event.series_id.as_ref() == Some(*series_id))
+            }))
+            .map(|id| id.as_str())
+            .collect::<Vec<_>>();
+        unmatched.sort();
+        unmatched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> crate::events::IngestedEvent {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn later_wins_and_bad_values_fail() {
+        let path = std::env::temp_dir().join(format!("label_policies_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [series.ac]
+            default_setting = "hide"
+            blurs = "media"
+
+            [events.ac-2025]
+            blurs = "content"
+
+            [events.gone-2020]
+            adult_only = true
+            "#,
+        )
+        .unwrap();
+        let overrides = Overrides::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let ac = event(
+            r#"{"id":"ac-2025","name":"AC 2025","venue":"","locale":"en","startDate":"2025-07-03","endDate":"2025-07-06","seriesId":"ac","labelPolicy":{"adultOnly":true,"defaultSetting":"ignore","severity":"alert"}}"#,
+        );
+        assert_eq!(
+            overrides.for_event(&ac),
+            Policy {
+                blurs: Some(Blurs::Content),
+                severity: Some(Severity::Alert),
+                default_setting: Some(DefaultSetting::Hide),
+                adult_only: Some(true),
+            }
+        );
+        assert_eq!(overrides.for_series("ac", &ac).blurs, Some(Blurs::Media));
+        assert_eq!(overrides.unmatched([&ac].into_iter()), vec!["gone-2020"]);
+
+        let def = Policy::default().definition("x".to_string(), vec![]);
+        assert_eq!(
+            (
+                def.blurs.as_str(),
+                def.severity.as_str(),
+                def.default_setting.as_deref(),
+                def.adult_only
+            ),
+            ("none", "inform", Some("warn"), Some(false))
+        );
+
+        assert!(serde_json::from_str::<Policy>(r#"{"blurs":"everything"}"#).is_err());
+        assert!(serde_json::from_str::<Policy>(r#"{"defaultSetting":"show"}"#).is_err());
+        assert!(serde_json::from_str::<Policy>(r#"{"severity":"Inform"}"#).is_err());
+        assert!(serde_json::from_str::<Policy>(r#"{"adultonly":true}"#).is_err());
+    }
+}
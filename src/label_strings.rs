@@ -0,0 +1,306 @@
+//! The names and descriptions on an event's label definition: one entry for
+//! the event's own locale, plus one per language in the feed's
+//! `localizations`. A localization without a description gets the usual
+//! dates-and-place one, with the dates written the way that language writes
+//! them. A series' definition gets the same languages from its latest
+//! edition.
+
+/// `start`–`end` as written in `lang` (a BCP 47 tag). Japanese and Chinese
+/// get `2025年7月3日～6日`, Korean `2025년 7월 3일~6일`, English
+/// `July 3–6, 2025` (day first outside the US); anything else stays ISO.
+pub fn format_dates(lang: &str, start: chrono::NaiveDate, end: chrono::NaiveDate) -> String {
+    use chrono::Datelike as _;
+
+    let mut subtags = lang.split(['-', '_']);
+    let language = subtags.next().unwrap_or_default().to_ascii_lowercase();
+    let region = subtags
+        .find(|subtag| subtag.len() == 2)
+        .map(|region| region.to_ascii_uppercase());
+
+    let same_year = start.year() == end.year();
+    let same_month = same_year && start.month() == end.month();
+    let same_day = start == end;
+
+    match language.as_str() {
+        "ja" | "zh" => {
+            let start = format!("{}年{}月{}日", start.year(), start.month(), start.day());
+            let end = if same_month {
+                format!("{}日", end.day())
+            } else if same_year {
+                format!("{}月{}日", end.month(), end.day())
+            } else {
+                format!("{}年{}月{}日", end.year(), end.month(), end.day())
+            };
+            if same_day {
+                start
+            } else {
+                format!("{start}～{end}")
+            }
+        }
+        "ko" => {
+            let start = format!("{}년 {}월 {}일", start.year(), start.month(), start.day());
+            let end = if same_month {
+                format!("{}일", end.day())
+            } else if same_year {
+                format!("{}월 {}일", end.month(), end.day())
+            } else {
+                format!("{}년 {}월 {}일", end.year(), end.month(), end.day())
+            };
+            if same_day {
+                start
+            } else {
+                format!("{start}~{end}")
+            }
+        }
+        "en" if region.as_deref().is_none_or(|region| region == "US") => {
+            if same_day {
+                start.format("%B %-d, %Y").to_string()
+            } else if same_month {
+                format!("{}–{}", start.format("%B %-d"), end.format("%-d, %Y"))
+            } else if same_year {
+                format!("{} – {}", start.format("%B %-d"), end.format("%B %-d, %Y"))
+            } else {
+                format!(
+                    "{} – {}",
+                    start.format("%B %-d, %Y"),
+                    end.format("%B %-d, %Y")
+                )
+            }
+        }
+        "en" => {
+            if same_day {
+                start.format("%-d %B %Y").to_string()
+            } else if same_month {
+                format!("{}–{}", start.format("%-d"), end.format("%-d %B %Y"))
+            } else if same_year {
+                format!("{} – {}", start.format("%-d %B"), end.format("%-d %B %Y"))
+            } else {
+                format!(
+                    "{} – {}",
+                    start.format("%-d %B %Y"),
+                    end.format("%-d %B %Y")
+                )
+            }
+        }
+        _ => format!("{start} – {end}"),
+    }
+}
+
+/// The default description: dates (as `lang` writes them) and place.
+pub fn description(lang: &str, event: &crate::events::IngestedEvent) -> String {
+    let mut location = event.venue.clone();
+    if let Some(address) = &event.address {
+        location.push_str(", ");
+        location.push_str(address);
+    }
+    format!(
+        "📅 {dates}\n📍 {location}",
+        dates = format_dates(lang, event.start_date, event.end_date)
+    )
+}
+
+/// The definition's `locales`: the event's own locale first, then its
+/// localizations by language. A localization for the event's own locale
+/// replaces it; one whose language tag doesn't parse is skipped.
+pub fn locales(
+    event: &crate::events::IngestedEvent,
+) -> Vec<atrium_api::com::atproto::label::defs::LabelValueDefinitionStrings> {
+    localized(event)
+        .map(|(lang, language, name, description)| {
+            atrium_api::com::atproto::label::defs::LabelValueDefinitionStringsData {
+                lang: language,
+                name: name.clone(),
+                description: description
+                    .cloned()
+                    .unwrap_or_else(|| self::description(lang, event)),
+            }
+            .into()
+        })
+        .collect()
+}
+
+/// A series definition's description in `lang`: Japanese, Chinese and
+/// Korean get their own wording; anything else the English.
+pub fn series_description(lang: &str, name: &str) -> String {
+    let language = lang
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match language.as_str() {
+        "ja" => format!("🔁 {name}のすべての回"),
+        "zh" => format!("🔁 {name}的任何一届"),
+        "ko" => format!("🔁 {name}의 모든 회차"),
+        _ => format!("🔁 Any edition of {name}"),
+    }
+}
+
+/// The `locales` of a series' definition, from its latest edition `event`:
+/// the same languages as `locales`, each name without its year. The
+/// edition's own descriptions are about that edition, so each language gets
+/// `series_description` instead.
+pub fn series_locales(
+    event: &crate::events::IngestedEvent,
+) -> Vec<atrium_api::com::atproto::label::defs::LabelValueDefinitionStrings> {
+    localized(event)
+        .map(|(lang, language, name, _)| {
+            let name = crate::like_labels::series_name(name);
+            atrium_api::com::atproto::label::defs::LabelValueDefinitionStringsData {
+                lang: language,
+                description: series_description(lang, &name),
+                name,
+            }
+            .into()
+        })
+        .collect()
+}
+
+/// `(lang, lang as a Language, name, description)` for the event's own locale
+/// and each of its localizations, in `locales` order.
+fn localized(
+    event: &crate::events::IngestedEvent,
+) -> impl Iterator<
+    Item = (
+        &String,
+        atrium_api::types::string::Language,
+        &String,
+        Option<&String>,
+    ),
+> {
+    let own = event.localizations.get(&event.locale);
+    std::iter::once((
+        &event.locale,
+        own.map_or(&event.name, |localized| &localized.name),
+        own.and_then(|localized| localized.description.as_ref()),
+    ))
+    .chain(
+        event
+            .localizations
+            .iter()
+            .filter(|(lang, _)| **lang != event.locale)
+            .map(|(lang, localized)| (lang, &localized.name, localized.description.as_ref())),
+    )
+    .filter_map(|(lang, name, description)| {
+        let Ok(language) = atrium_api::types::string::Language::new(lang.clone()) else {
+            log::warn!(
+                "{}: skipping localization {lang:?}, not a language tag",
+                event.id
+            );
+            return None;
+        };
+        Some((lang, language, name, description))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> chrono::NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn dates_per_language() {
+        for (lang, start, end, want) in [
+            ("ja", "2025-07-03", "2025-07-06", "2025年7月3日～6日"),
+            ("ja-JP", "2025-07-30", "2025-08-02", "2025年7月30日～8月2日"),
+            (
+                "zh-Hant-TW",
+                "2025-12-30",
+                "2026-01-02",
+                "2025年12月30日～2026年1月2日",
+            ),
+            ("ja", "2025-07-03", "2025-07-03", "2025年7月3日"),
+            ("ko", "2025-07-03", "2025-07-06", "2025년 7월 3일~6일"),
+            ("en", "2025-07-03", "2025-07-06", "July 3–6, 2025"),
+            (
+                "en-US",
+                "2025-07-30",
+                "2025-08-02",
+                "July 30 – August 2, 2025",
+            ),
+            (
+                "en",
+                "2025-12-30",
+                "2026-01-02",
+                "December 30, 2025 – January 2, 2026",
+            ),
+            ("en-GB", "2025-07-03", "2025-07-06", "3–6 July 2025"),
+            (
+                "en-AU",
+                "2025-07-30",
+                "2025-08-02",
+                "30 July – 2 August 2025",
+            ),
+            ("de", "2025-07-03", "2025-07-06", "2025-07-03 – 2025-07-06"),
+        ] {
+            assert_eq!(format_dates(lang, date(start), date(end)), want, "{lang}");
+        }
+    }
+
+    #[test]
+    fn one_entry_per_language() {
+        let event: crate::events::IngestedEvent = serde_json::from_str(
+            r#"{"id":"jmof-2025","name":"JMoF 2025","venue":"Hotel","locale":"ja","startDate":"2025-02-07","endDate":"2025-02-09","localizations":{"ja":{"name":"ジャパンミーティングオブファーリーズ2025"},"en":{"name":"Japan Meeting of Furries 2025"},"fr":{"name":"JMoF","description":"Février"},"not a tag":{"name":"x"}}}"#,
+        )
+        .unwrap();
+        let locales = locales(&event);
+        assert_eq!(
+            locales
+                .iter()
+                .map(|strings| (
+                    strings.lang.as_ref().as_str().to_string(),
+                    strings.name.as_str(),
+                    strings.description.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "ja".to_string(),
+                    "ジャパンミーティングオブファーリーズ2025",
+                    "📅 2025年2月7日～9日\n📍 Hotel"
+                ),
+                (
+                    "en".to_string(),
+                    "Japan Meeting of Furries 2025",
+                    "📅 February 7–9, 2025\n📍 Hotel"
+                ),
+                ("fr".to_string(), "JMoF", "Février"),
+            ]
+        );
+    }
+
+    #[test]
+    fn series_names_per_language() {
+        let event: crate::events::IngestedEvent = serde_json::from_str(
+            r#"{"id":"jmof-2025","name":"JMoF 2025","venue":"Hotel","locale":"ja","startDate":"2025-02-07","endDate":"2025-02-09","localizations":{"ja":{"name":"ジャパンミーティングオブファーリーズ2025"},"en":{"name":"Japan Meeting of Furries 2025","description":"February"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(series_description("zh-Hant", "FF"), "🔁 FF的任何一届");
+        assert_eq!(series_description("ko", "KFF"), "🔁 KFF의 모든 회차");
+        assert_eq!(series_description("de", "EF"), "🔁 Any edition of EF");
+        assert_eq!(
+            series_locales(&event)
+                .iter()
+                .map(|strings| (
+                    strings.lang.as_ref().as_str().to_string(),
+                    strings.name.as_str(),
+                    strings.description.as_str()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "ja".to_string(),
+                    "ジャパンミーティングオブファーリーズ",
+                    "🔁 ジャパンミーティングオブファーリーズのすべての回"
+                ),
+                (
+                    "en".to_string(),
+                    "Japan Meeting of Furries",
+                    "🔁 Any edition of Japan Meeting of Furries"
+                ),
+            ]
+        );
+    }
+}
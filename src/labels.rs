@@ -22,6 +22,11 @@ pub fn sign_to_payload(
     Ok(serde_ipld_dagcbor::to_vec(&label)?)
 }
 
+/// The like rkey recorded for labels applied by hand: no like ever has it,
+/// so an unlike never takes one of these off, and nothing reconciling
+/// labels against likes touches them.
+pub const ADMIN_LIKE_RKEY: &str = "admin";
+
 #[derive(thiserror::Error, Debug)]
 pub enum EmitError {
     #[error("sign: {0}")]
@@ -31,28 +36,1092 @@ pub enum EmitError {
     Sqlx(#[from] sqlx::Error),
 }
 
+/// Sign `label`, what a like (or unlike) asks for, and append it to the log,
+/// unless it was asked for before or would change nothing. Jetstream replays
+/// up to an hour on failover, so the same like can arrive more than once,
+/// even after its unlike: a positive label goes out once per `(uri,
+/// like_rkey, val)`, and a negation only while the label is on. No timestamps
+/// are compared, as firehose `time_us` and our own re-issues' `cts` don't
+/// share a clock. Returns the new seq, or `None` when nothing was emitted.
 pub async fn emit(
     keypair: &atrium_crypto::keypair::Secp256k1Keypair,
     tx: &mut sqlx::PgTransaction<'_>,
     label: &atrium_api::com::atproto::label::defs::Label,
     like_rkey: &str,
-) -> Result<i64, EmitError> {
+) -> Result<Option<i64>, EmitError> {
+    lock(tx).await?;
+
+    if !label.neg.unwrap_or(false)
+        && sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM labels WHERE like_rkey = $1 AND uri = $2 AND val = $3 AND NOT neg
+            ) AS "given!"
+            "#,
+            like_rkey,
+            label.uri,
+            label.val,
+        )
+        .fetch_one(&mut **tx)
+        .await?
+    {
+        return Ok(None);
+    }
+
+    reissue(keypair, tx, label, like_rkey).await
+}
+
+/// Serialize emitters so seqs become visible in order: subscribeLabels
+/// streams `seq > last`, and would skip for good a lower seq that committed
+/// after a higher one. Readers are not blocked.
+async fn lock(tx: &mut sqlx::PgTransaction<'_>) -> Result<(), sqlx::Error> {
+    sqlx::query!("LOCK TABLE labels IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Sign `label`, decided here rather than asked for by a like (a restore, a
+/// promotion, a new expiry, a hand edit), and append it to the log unless it
+/// would leave `(uri, val)` as it is.
+async fn reissue(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    label: &atrium_api::com::atproto::label::defs::Label,
+    like_rkey: &str,
+) -> Result<Option<i64>, EmitError> {
+    lock(tx).await?;
+
+    let neg = label.neg.unwrap_or(false);
+    let exp = label.exp.as_ref().map(|exp| exp.as_ref().to_utc());
+    // Checked first, so a no-op doesn't spend a seq and leave a gap.
+    if sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM current_labels
+            WHERE uri = $1 AND val = $2
+                AND (neg, exp, like_rkey) IS NOT DISTINCT FROM ($3, $4, $5)
+        ) AS "unchanged!"
+        "#,
+        label.uri,
+        label.val,
+        neg,
+        exp,
+        like_rkey,
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    {
+        return Ok(None);
+    }
+
     let seq = sqlx::query_scalar!(
         r#"
-        INSERT INTO labels (val, uri, neg, payload, like_rkey)
-        VALUES ($1, $2, $3, $4, $5)
+        INSERT INTO current_labels (uri, val, neg, exp, like_rkey, cts, seq)
+        VALUES ($1, $2, $3, $4, $5, $6, nextval('labels_seq_seq'))
+        ON CONFLICT (uri, val) DO UPDATE
+        SET neg = excluded.neg, exp = excluded.exp, like_rkey = excluded.like_rkey,
+            cts = excluded.cts, seq = excluded.seq
         RETURNING seq
         "#,
+        label.uri,
+        label.val,
+        neg,
+        exp,
+        like_rkey,
+        label.cts.as_ref().to_utc(),
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    append(keypair, tx, seq, label, like_rkey).await?;
+
+    sqlx::query!("NOTIFY labels").execute(&mut **tx).await?;
+
+    Ok(Some(seq))
+}
+
+/// Sign `label` into the log at `seq`.
+async fn append(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    seq: i64,
+    label: &atrium_api::com::atproto::label::defs::Label,
+    like_rkey: &str,
+) -> Result<(), EmitError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO labels (seq, val, uri, neg, payload, like_rkey)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        seq,
         label.val,
         label.uri,
         label.neg.unwrap_or(false),
         sign_to_payload(keypair, label)?,
         like_rkey,
     )
-    .fetch_one(&mut **tx)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Re-issue every label in effect, signed with `keypair`: after a key
+/// rotation, clients stop verifying what the old key signed. Each gets a new
+/// seq and `cts`, with its `exp` and like kept. `emit` would skip these, as
+/// the state does not change, so they go straight to the log. Returns the
+/// number re-issued.
+pub async fn resign_current(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+) -> Result<u64, EmitError> {
+    lock(tx).await?;
+
+    let cts = chrono::Utc::now();
+    let mut reissued = sqlx::query!(
+        r#"
+        UPDATE current_labels
+        SET cts = $1, seq = nextval('labels_seq_seq')
+        WHERE NOT neg AND (exp IS NULL OR exp > $1)
+        RETURNING uri, val, exp, like_rkey, seq
+        "#,
+        cts,
+    )
+    .fetch_all(&mut **tx)
     .await?;
 
+    reissued.sort_by_key(|row| row.seq);
+    for row in &reissued {
+        let label = atrium_api::com::atproto::label::defs::LabelData {
+            cts: atrium_api::types::string::Datetime::new(cts.fixed_offset()),
+            exp: row
+                .exp
+                .map(|exp| atrium_api::types::string::Datetime::new(exp.fixed_offset())),
+            src: src.clone(),
+            cid: None,
+            neg: None,
+            uri: row.uri.clone(),
+            val: row.val.clone(),
+            sig: None,
+            ver: Some(1),
+        }
+        .into();
+        append(keypair, tx, row.seq, &label, &row.like_rkey).await?;
+    }
+
     sqlx::query!("NOTIFY labels").execute(&mut **tx).await?;
 
-    Ok(seq)
+    Ok(reissued.len() as u64)
+}
+
+/// A label that is in effect right now: its latest emission for the
+/// `(uri, val)` is positive and not past its `exp`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentLabel {
+    pub uri: String,
+    pub val: String,
+    pub exp: Option<chrono::DateTime<chrono::Utc>>,
+    /// The like it is on for: the one that applied it, unless that was
+    /// unliked while another still gives it (see `like_labels::unlike`).
+    pub like_rkey: String,
+    pub seq: i64,
+}
+
+/// Labels currently in effect on `uri` (for us, always a DID), by `val`.
+pub async fn current(
+    db_conn: &mut sqlx::PgConnection,
+    uri: &str,
+) -> Result<Vec<CurrentLabel>, sqlx::Error> {
+    sqlx::query_as!(
+        CurrentLabel,
+        r#"
+        SELECT uri, val, exp, like_rkey, seq
+        FROM current_labels
+        WHERE uri = $1 AND NOT neg AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+        ORDER BY val
+        "#,
+        uri
+    )
+    .fetch_all(db_conn)
+    .await
+}
+
+/// Labels with any of `vals` currently in effect, on anyone.
+pub async fn current_with_vals(
+    db_conn: &mut sqlx::PgConnection,
+    vals: &[String],
+) -> Result<Vec<CurrentLabel>, sqlx::Error> {
+    sqlx::query_as!(
+        CurrentLabel,
+        r#"
+        SELECT uri, val, exp, like_rkey, seq
+        FROM current_labels
+        WHERE val = ANY($1) AND NOT neg AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+        ORDER BY uri, val
+        "#,
+        vals
+    )
+    .fetch_all(db_conn)
+    .await
+}
+
+/// An unsigned label from us, issued now.
+pub fn new_label(
+    src: &atrium_api::types::string::Did,
+    uri: &str,
+    val: &str,
+    neg: bool,
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+) -> atrium_api::com::atproto::label::defs::Label {
+    atrium_api::com::atproto::label::defs::LabelData {
+        cts: atrium_api::types::string::Datetime::now(),
+        exp: exp.map(|exp| atrium_api::types::string::Datetime::new(exp.fixed_offset())),
+        src: src.clone(),
+        cid: None,
+        neg: neg.then_some(true),
+        uri: uri.to_string(),
+        val: val.to_string(),
+        sig: None,
+        ver: Some(1),
+    }
+    .into()
+}
+
+/// Put `val` on `uri` now, on behalf of `like_rkey`. Unlike `emit`, goes
+/// through for a like that gave `val` before, e.g. to restore it.
+pub async fn apply(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    uri: &str,
+    val: &str,
+    exp: Option<chrono::DateTime<chrono::Utc>>,
+    like_rkey: &str,
+) -> Result<Option<i64>, EmitError> {
+    reissue(
+        keypair,
+        tx,
+        &new_label(src, uri, val, false, exp),
+        like_rkey,
+    )
+    .await
+}
+
+/// Take `current` off its subject now, on behalf of the like that applied it.
+pub async fn negate(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    current: &CurrentLabel,
+) -> Result<Option<i64>, EmitError> {
+    reissue(
+        keypair,
+        tx,
+        &new_label(src, &current.uri, &current.val, true, None),
+        &current.like_rkey,
+    )
+    .await
+}
+
+/// Follow a change in `val`'s event expiry (the event's end date or time zone
+/// moved): record `exp` as the expiry labels for `val` get, and if that
+/// differs from what was last recorded, re-issue with `exp` the positive
+/// labels still carrying the old one, including those it already expired.
+/// Labels with any other `exp` were set by hand and are left alone. The first
+/// call for a `val` only records. Returns the number re-issued.
+pub async fn follow_event_exp(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    val: &str,
+    exp: chrono::DateTime<chrono::Utc>,
+) -> Result<u64, EmitError> {
+    let Some(old_exp) =
+        sqlx::query_scalar!(r#"SELECT exp FROM event_expiries WHERE val = $1"#, val)
+            .fetch_optional(&mut **tx)
+            .await?
+    else {
+        sqlx::query!(
+            r#"INSERT INTO event_expiries (val, exp) VALUES ($1, $2)"#,
+            val,
+            exp
+        )
+        .execute(&mut **tx)
+        .await?;
+        return Ok(0);
+    };
+    if old_exp == exp {
+        return Ok(0);
+    }
+
+    let stale = sqlx::query!(
+        r#"
+        SELECT uri, like_rkey
+        FROM current_labels
+        WHERE val = $1 AND NOT neg AND exp = $2
+        ORDER BY uri
+        "#,
+        val,
+        old_exp,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+    let mut n = 0;
+    for row in &stale {
+        if apply(keypair, tx, src, &row.uri, val, Some(exp), &row.like_rkey)
+            .await?
+            .is_some()
+        {
+            n += 1;
+        }
+    }
+
+    sqlx::query!(
+        r#"UPDATE event_expiries SET exp = $2 WHERE val = $1"#,
+        val,
+        exp
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(n)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RebuildError {
+    #[error("serde_ipld_dagcbor: {0}")]
+    SerdeIpldDagcbor(#[from] serde_ipld_dagcbor::DecodeError<std::convert::Infallible>),
+
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+}
+
+/// Repopulate `current_labels` from the log when it is empty but the log is
+/// not: the state a deployment is in right after the table is added. `exp`
+/// lives only in the signed payload, hence Rust rather than one INSERT …
+/// SELECT. Returns the number of rows written.
+pub async fn rebuild_current_if_empty(
+    tx: &mut sqlx::PgTransaction<'_>,
+) -> Result<u64, RebuildError> {
+    if sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM current_labels) OR NOT EXISTS (SELECT 1 FROM labels) AS "skip!"
+        "#
+    )
+    .fetch_one(&mut **tx)
+    .await?
+    {
+        return Ok(0);
+    }
+
+    let latest = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (uri, val) uri, val, neg, payload, like_rkey, seq
+        FROM labels
+        ORDER BY uri, val, seq DESC
+        "#
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let mut n = 0;
+    for row in latest {
+        let label: atrium_api::com::atproto::label::defs::Label =
+            serde_ipld_dagcbor::from_slice(&row.payload)?;
+        sqlx::query!(
+            r#"
+            INSERT INTO current_labels (uri, val, neg, exp, like_rkey, cts, seq)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            row.uri,
+            row.val,
+            row.neg,
+            label.exp.as_ref().map(|exp| exp.as_ref().to_utc()),
+            row.like_rkey,
+            label.cts.as_ref().to_utc(),
+            row.seq,
+        )
+        .execute(&mut **tx)
+        .await?;
+        n += 1;
+    }
+    Ok(n)
+}
+
+/// Something wrong with a stored label row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditFinding {
+    /// The payload does not decode as a label.
+    Corrupt(String),
+    Unsigned,
+    /// Signed, but by none of the keys given.
+    BadSignature,
+    /// An indexed column disagrees with what was signed.
+    Mismatch {
+        column: &'static str,
+        stored: String,
+        signed: String,
+    },
+}
+
+impl std::fmt::Display for AuditFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditFinding::Corrupt(e) => write!(f, "corrupt payload: {e}"),
+            AuditFinding::Unsigned => write!(f, "unsigned"),
+            AuditFinding::BadSignature => write!(f, "signature does not verify"),
+            AuditFinding::Mismatch {
+                column,
+                stored,
+                signed,
+            } => write!(f, "{column} is {stored:?}, signed {signed:?}"),
+        }
+    }
+}
+
+/// Re-check one row of the log: that `payload` decodes, carries a signature
+/// from one of `keys` (`did:key`s, e.g. the current and pre-rotation keys),
+/// and agrees with the row's `val`, `uri` and `neg`.
+pub fn audit_payload(
+    val: &str,
+    uri: &str,
+    neg: bool,
+    payload: &[u8],
+    keys: &[String],
+) -> Vec<AuditFinding> {
+    let mut label: atrium_api::com::atproto::label::defs::Label =
+        match serde_ipld_dagcbor::from_slice(payload) {
+            Ok(label) => label,
+            Err(e) => return vec![AuditFinding::Corrupt(e.to_string())],
+        };
+
+    let mut findings = vec![];
+    for (column, stored, signed) in [
+        ("val", val.to_string(), label.val.clone()),
+        ("uri", uri.to_string(), label.uri.clone()),
+        (
+            "neg",
+            neg.to_string(),
+            label.neg.unwrap_or(false).to_string(),
+        ),
+    ] {
+        if stored != signed {
+            findings.push(AuditFinding::Mismatch {
+                column,
+                stored,
+                signed,
+            });
+        }
+    }
+
+    match label.sig.take() {
+        None => findings.push(AuditFinding::Unsigned),
+        Some(sig) => {
+            let verified = serde_ipld_dagcbor::to_vec(&label).is_ok_and(|msg| {
+                keys.iter()
+                    .any(|key| atrium_crypto::verify::verify_signature(key, &msg, &sig).is_ok())
+            });
+            if !verified {
+                findings.push(AuditFinding::BadSignature);
+            }
+        }
+    }
+    findings
+}
+
+/// What one `compact` pass removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compaction {
+    pub pruned: u64,
+    /// Highest seq deleted so far, across all passes.
+    pub horizon: i64,
+}
+
+/// Delete log rows no subscriber needs any more. For each `(uri, val)`, once
+/// its latest emission is older than `grace`, every earlier row is superseded;
+/// and once that latest emission is itself a negation older than `grace`, or
+/// a positive label `grace` past its `exp`, the `(uri, val)` is forgotten
+/// entirely, projection row included. A subscriber has had `grace` to see
+/// each of these; one that comes back with an older cursor is told so (see
+/// `subscribe_labels`), as seqs are never reused.
+pub async fn compact(
+    db_pool: &sqlx::PgPool,
+    grace: chrono::Duration,
+) -> Result<Compaction, sqlx::Error> {
+    let cutoff = chrono::Utc::now() - grace;
+    let mut tx = db_pool.begin().await?;
+
+    let pruned = sqlx::query!(
+        r#"
+        WITH pruned AS (
+            DELETE FROM labels
+            USING current_labels
+            WHERE labels.uri = current_labels.uri AND labels.val = current_labels.val
+                AND (
+                    (labels.seq < current_labels.seq AND current_labels.cts < $1)
+                    OR (
+                        labels.seq = current_labels.seq
+                        AND ((current_labels.neg AND current_labels.cts < $1) OR current_labels.exp < $1)
+                    )
+                )
+            RETURNING labels.seq
+        )
+        SELECT COUNT(*) AS "n!", MAX(seq) AS horizon FROM pruned
+        "#,
+        cutoff,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        DELETE FROM current_labels
+        WHERE (neg AND cts < $1) OR exp < $1
+        "#,
+        cutoff,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let horizon = sqlx::query_scalar!(
+        r#"
+        INSERT INTO labels_compaction (horizon) VALUES (COALESCE($1::BIGINT, 0))
+        ON CONFLICT ((true)) DO UPDATE
+        SET horizon = GREATEST(labels_compaction.horizon, excluded.horizon)
+        RETURNING horizon
+        "#,
+        pruned.horizon,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(Compaction {
+        pruned: pruned.n as u64,
+        horizon,
+    })
+}
+
+/// Run `compact` every `every`, forever. A failed pass is logged and retried
+/// on the next tick.
+pub async fn periodic_compact(
+    db_pool: &sqlx::PgPool,
+    every: std::time::Duration,
+    grace: chrono::Duration,
+) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        match compact(db_pool, grace).await {
+            Ok(Compaction { pruned, horizon }) => {
+                log::info!("compacted labels: pruned {pruned} row(s), horizon now seq {horizon}")
+            }
+            Err(e) => log::error!("failed to compact labels: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(
+        uri: &str,
+        val: &str,
+        neg: bool,
+        exp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> atrium_api::com::atproto::label::defs::Label {
+        label_at(chrono::Utc::now(), uri, val, neg, exp)
+    }
+
+    fn label_at(
+        cts: chrono::DateTime<chrono::Utc>,
+        uri: &str,
+        val: &str,
+        neg: bool,
+        exp: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> atrium_api::com::atproto::label::defs::Label {
+        atrium_api::com::atproto::label::defs::LabelData {
+            cts: atrium_api::types::string::Datetime::new(cts.fixed_offset()),
+            exp: exp.map(|exp| atrium_api::types::string::Datetime::new(exp.fixed_offset())),
+            src: atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap(),
+            cid: None,
+            neg: neg.then_some(true),
+            uri: uri.to_string(),
+            val: val.to_string(),
+            sig: None,
+            ver: Some(1),
+        }
+        .into()
+    }
+
+    async fn emit_all(
+        db_pool: &sqlx::PgPool,
+        labels: &[(atrium_api::com::atproto::label::defs::Label, &str)],
+    ) {
+        let keypair = crate::test_db::keypair();
+        for (label, like_rkey) in labels {
+            let mut tx = db_pool.begin().await.unwrap();
+            emit(&keypair, &mut tx, label, like_rkey).await.unwrap();
+            tx.commit().await.unwrap();
+        }
+    }
+
+    fn vals(current: &[CurrentLabel]) -> Vec<&str> {
+        current.iter().map(|c| c.val.as_str()).collect()
+    }
+
+    #[test]
+    fn audit_finds_tampering() {
+        use atrium_crypto::keypair::Did as _;
+
+        let keypair = crate::test_db::keypair();
+        let keys = [keypair.did()];
+        let payload = sign_to_payload(&keypair, &label("did:plc:a", "alpha", false, None)).unwrap();
+
+        assert_eq!(
+            audit_payload("alpha", "did:plc:a", false, &payload, &keys),
+            vec![]
+        );
+        assert_eq!(
+            audit_payload("beta", "did:plc:a", true, &payload, &keys),
+            vec![
+                AuditFinding::Mismatch {
+                    column: "val",
+                    stored: "beta".to_string(),
+                    signed: "alpha".to_string(),
+                },
+                AuditFinding::Mismatch {
+                    column: "neg",
+                    stored: "true".to_string(),
+                    signed: "false".to_string(),
+                },
+            ]
+        );
+        let other = atrium_crypto::keypair::Secp256k1Keypair::import(&[2; 32]).unwrap();
+        assert_eq!(
+            audit_payload("alpha", "did:plc:a", false, &payload, &[other.did()]),
+            vec![AuditFinding::BadSignature]
+        );
+        assert!(matches!(
+            audit_payload("alpha", "did:plc:a", false, &payload[1..], &keys)[..],
+            [AuditFinding::Corrupt(_)]
+        ));
+
+        // Re-encoded with the val swapped, signature kept.
+        let mut forged: atrium_api::com::atproto::label::defs::Label =
+            serde_ipld_dagcbor::from_slice(&payload).unwrap();
+        forged.val = "beta".to_string();
+        let forged = serde_ipld_dagcbor::to_vec(&forged).unwrap();
+        assert_eq!(
+            audit_payload("beta", "did:plc:a", false, &forged, &keys),
+            vec![AuditFinding::BadSignature]
+        );
+
+        let unsigned =
+            serde_ipld_dagcbor::to_vec(&label("did:plc:a", "alpha", false, None)).unwrap();
+        assert_eq!(
+            audit_payload("alpha", "did:plc:a", false, &unsigned, &keys),
+            vec![AuditFinding::Unsigned]
+        );
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // Like/unlike cycles, replayed duplicates and expired labels all collapse
+    // to what is in effect now.
+    #[tokio::test]
+    #[ignore]
+    async fn current_resolves_negations_and_expiry() {
+        let db_pool = crate::test_db::pool().await;
+        // Whole seconds: Datetime rounds to the microsecond, Postgres truncates.
+        let later =
+            chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0) + chrono::Days::new(30);
+        let earlier = chrono::Utc::now() - chrono::Days::new(1);
+        emit_all(
+            &db_pool,
+            &[
+                // liked, unliked, liked again
+                (label("did:plc:a", "alpha", false, Some(later)), "3ka"),
+                (label("did:plc:a", "alpha", true, None), "3ka"),
+                (label("did:plc:a", "alpha", false, Some(later)), "3kb"),
+                // liked, then unliked
+                (label("did:plc:a", "beta", false, Some(later)), "3kc"),
+                (label("did:plc:a", "beta", true, None), "3kc"),
+                // replayed
+                (label("did:plc:a", "gamma", false, Some(later)), "3kd"),
+                (label("did:plc:a", "gamma", false, Some(later)), "3kd"),
+                // over
+                (label("did:plc:a", "delta", false, Some(earlier)), "3ke"),
+                // someone else
+                (label("did:plc:b", "beta", false, Some(later)), "3kf"),
+            ],
+        )
+        .await;
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let current_a = current(&mut db_conn, "did:plc:a").await.unwrap();
+        assert_eq!(vals(&current_a), vec!["alpha", "gamma"]);
+        assert_eq!(current_a[0].like_rkey, "3kb");
+        assert_eq!(current_a[0].exp, Some(later));
+        assert_eq!(
+            vals(&current(&mut db_conn, "did:plc:b").await.unwrap()),
+            vec!["beta"]
+        );
+
+        // Rebuilding from the log lands on the same state.
+        sqlx::query("DELETE FROM current_labels")
+            .execute(&mut *db_conn)
+            .await
+            .unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        assert_eq!(rebuild_current_if_empty(&mut tx).await.unwrap(), 5);
+        assert_eq!(rebuild_current_if_empty(&mut tx).await.unwrap(), 0);
+        tx.commit().await.unwrap();
+        assert_eq!(current(&mut db_conn, "did:plc:a").await.unwrap(), current_a);
+    }
+
+    // Synthetic history, a year old except where noted: only what is still in
+    // effect (or too recent to drop) survives, and the horizon records how
+    // far the log was cut.
+    #[tokio::test]
+    #[ignore]
+    async fn compact_prunes_superseded_and_expired() {
+        let db_pool = crate::test_db::pool().await;
+        let old = |days| chrono::Utc::now() - chrono::Days::new(365) + chrono::Days::new(days);
+        let now = chrono::Utc::now();
+        let later = Some(now + chrono::Days::new(30));
+        emit_all(
+            &db_pool,
+            &[
+                // liked, unliked long ago: all gone
+                (label_at(old(0), "did:plc:a", "alpha", false, later), "3ka"),
+                (label_at(old(1), "did:plc:a", "alpha", true, None), "3ka"),
+                // liked, unliked, liked again long ago: the last like stays
+                (label_at(old(0), "did:plc:a", "beta", false, later), "3kb"),
+                (label_at(old(1), "did:plc:a", "beta", true, None), "3kb"),
+                (label_at(old(2), "did:plc:a", "beta", false, later), "3kc"),
+                // expired long ago: gone
+                (
+                    label_at(old(0), "did:plc:a", "gamma", false, Some(old(10))),
+                    "3kd",
+                ),
+                // unliked just now: kept for subscribers to see
+                (label_at(old(0), "did:plc:a", "delta", false, later), "3ke"),
+                (label_at(now, "did:plc:a", "delta", true, None), "3ke"),
+            ],
+        )
+        .await;
+
+        let compaction = compact(&db_pool, chrono::Duration::days(7)).await.unwrap();
+        assert_eq!(compaction.pruned, 5);
+
+        let rows: Vec<(i64, String, bool)> =
+            sqlx::query_as("SELECT seq, val, neg FROM labels ORDER BY seq")
+                .fetch_all(&db_pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (5, "beta".to_string(), false),
+                (7, "delta".to_string(), false),
+                (8, "delta".to_string(), true),
+            ]
+        );
+        assert_eq!(compaction.horizon, 6);
+
+        let projected: Vec<String> =
+            sqlx::query_scalar("SELECT val FROM current_labels ORDER BY val")
+                .fetch_all(&db_pool)
+                .await
+                .unwrap();
+        assert_eq!(projected, vec!["beta", "delta"]);
+
+        // Nothing more to do; the horizon never moves back.
+        assert_eq!(
+            compact(&db_pool, chrono::Duration::days(7)).await.unwrap(),
+            Compaction {
+                pruned: 0,
+                horizon: 6
+            }
+        );
+    }
+
+    // Everyone's labels for a val go, and nothing else does.
+    #[tokio::test]
+    #[ignore]
+    async fn negate_current_with_vals() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let later = Some(chrono::Utc::now() + chrono::Days::new(30));
+        emit_all(
+            &db_pool,
+            &[
+                (label("did:plc:a", "alpha", false, later), "3ka"),
+                (label("did:plc:a", "beta", false, later), "3kb"),
+                (label("did:plc:b", "alpha", false, later), "3kc"),
+                (label("did:plc:c", "alpha", false, later), "3kd"),
+                (label("did:plc:c", "alpha", true, None), "3kd"),
+            ],
+        )
+        .await;
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let doomed = current_with_vals(&mut db_conn, &["alpha".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            doomed.iter().map(|c| c.uri.as_str()).collect::<Vec<_>>(),
+            vec!["did:plc:a", "did:plc:b"]
+        );
+
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        for current in &doomed {
+            assert!(negate(&keypair, &mut tx, &src, current)
+                .await
+                .unwrap()
+                .is_some());
+        }
+        tx.commit().await.unwrap();
+
+        assert_eq!(
+            vals(&current(&mut db_conn, "did:plc:a").await.unwrap()),
+            vec!["beta"]
+        );
+        assert!(current(&mut db_conn, "did:plc:b").await.unwrap().is_empty());
+        let like_rkey: String =
+            sqlx::query_scalar("SELECT like_rkey FROM labels WHERE uri = 'did:plc:b' AND neg")
+                .fetch_one(&mut *db_conn)
+                .await
+                .unwrap();
+        assert_eq!(like_rkey, "3kc");
+    }
+
+    // Labels carrying the event's old expiry move with it, expired or not;
+    // a hand-set expiry stays.
+    #[tokio::test]
+    #[ignore]
+    async fn event_exp_changes_reissue() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let now = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0);
+        let early = now - chrono::Days::new(1);
+        let moved = now + chrono::Days::new(30);
+        let by_hand = now + chrono::Days::new(300);
+        emit_all(
+            &db_pool,
+            &[
+                (label("did:plc:a", "alpha", false, Some(early)), "3ka"),
+                (label("did:plc:b", "alpha", false, Some(by_hand)), "admin"),
+                (label("did:plc:c", "alpha", false, Some(early)), "3kc"),
+                (label("did:plc:c", "alpha", true, None), "3kc"),
+            ],
+        )
+        .await;
+
+        let mut tx = db_pool.begin().await.unwrap();
+        assert_eq!(
+            follow_event_exp(&keypair, &mut tx, &src, "alpha", early)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            follow_event_exp(&keypair, &mut tx, &src, "alpha", early)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            follow_event_exp(&keypair, &mut tx, &src, "alpha", moved)
+                .await
+                .unwrap(),
+            1
+        );
+        tx.commit().await.unwrap();
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let alpha = current_with_vals(&mut db_conn, &["alpha".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(
+            alpha
+                .iter()
+                .map(|c| (c.uri.as_str(), c.exp, c.like_rkey.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("did:plc:a", Some(moved), "3ka"),
+                ("did:plc:b", Some(by_hand), "admin"),
+            ]
+        );
+    }
+
+    // After a rotation, what is in effect comes back signed by the new key
+    // under new seqs; negated and expired labels stay as they were.
+    #[tokio::test]
+    #[ignore]
+    async fn resign_reissues_what_is_in_effect() {
+        use atrium_crypto::keypair::Did as _;
+
+        let db_pool = crate::test_db::pool().await;
+        let later =
+            chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0) + chrono::Days::new(30);
+        let earlier = chrono::Utc::now() - chrono::Days::new(1);
+        emit_all(
+            &db_pool,
+            &[
+                (label("did:plc:a", "alpha", false, Some(later)), "3ka"),
+                (label("did:plc:a", "beta", false, Some(later)), "3kb"),
+                (label("did:plc:a", "beta", true, None), "3kb"),
+                (label("did:plc:a", "gamma", false, Some(earlier)), "3kc"),
+            ],
+        )
+        .await;
+
+        let new_keypair = atrium_crypto::keypair::Secp256k1Keypair::import(&[2; 32]).unwrap();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        assert_eq!(
+            resign_current(&new_keypair, &mut tx, &src).await.unwrap(),
+            1
+        );
+        tx.commit().await.unwrap();
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        let current_a = current(&mut db_conn, "did:plc:a").await.unwrap();
+        assert_eq!(vals(&current_a), vec!["alpha"]);
+        assert_eq!(current_a[0].seq, 5);
+        assert_eq!(current_a[0].exp, Some(later));
+        assert_eq!(current_a[0].like_rkey, "3ka");
+
+        let payload: Vec<u8> = sqlx::query_scalar("SELECT payload FROM labels WHERE seq = 5")
+            .fetch_one(&mut *db_conn)
+            .await
+            .unwrap();
+        let mut label: atrium_api::com::atproto::label::defs::Label =
+            serde_ipld_dagcbor::from_slice(&payload).unwrap();
+        let sig = label.sig.take().unwrap();
+        atrium_crypto::verify::verify_signature(
+            &new_keypair.did(),
+            &serde_ipld_dagcbor::to_vec(&label).unwrap(),
+            &sig,
+        )
+        .unwrap();
+    }
+
+    // A failover replays likes and unlikes with the new host's (slightly
+    // different) timestamps; none of them may reach the log again.
+    #[tokio::test]
+    #[ignore]
+    async fn replays_emit_nothing() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let exp = Some(chrono::Utc::now() + chrono::Days::new(30));
+        let t = |ms| {
+            chrono::Utc::now() - chrono::Duration::minutes(30) + chrono::Duration::milliseconds(ms)
+        };
+
+        let mut emitted = vec![];
+        for (label, like_rkey) in [
+            // like, then the other host's copy of it
+            (label_at(t(0), "did:plc:a", "alpha", false, exp), "3ka"),
+            (label_at(t(2), "did:plc:a", "alpha", false, exp), "3ka"),
+            // unlike
+            (label_at(t(60_000), "did:plc:a", "alpha", true, None), "3ka"),
+            // replay of the like and the unlike
+            (label_at(t(1), "did:plc:a", "alpha", false, exp), "3ka"),
+            (label_at(t(60_001), "did:plc:a", "alpha", true, None), "3ka"),
+            // a new like is not a replay
+            (
+                label_at(t(120_000), "did:plc:a", "alpha", false, exp),
+                "3kb",
+            ),
+        ] {
+            let mut tx = db_pool.begin().await.unwrap();
+            emitted.push(
+                emit(&keypair, &mut tx, &label, like_rkey)
+                    .await
+                    .unwrap()
+                    .is_some(),
+            );
+            tx.commit().await.unwrap();
+        }
+        assert_eq!(emitted, vec![true, false, true, false, false, true]);
+
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM labels")
+            .fetch_one(&db_pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 3);
+    }
+
+    // An unlike stamped (by the firehose) before a re-issue stamped here
+    // still takes the label off, and no-ops leave no gaps in the seqs.
+    #[tokio::test]
+    #[ignore]
+    async fn unlike_after_reissue_goes_through() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let exp = chrono::SubsecRound::trunc_subsecs(chrono::Utc::now(), 0) + chrono::Days::new(30);
+        let unliked_at = chrono::Utc::now() - chrono::Duration::minutes(5);
+
+        let mut tx = db_pool.begin().await.unwrap();
+        let mut seqs = vec![];
+        seqs.extend(
+            emit(
+                &keypair,
+                &mut tx,
+                &label_at(unliked_at, "did:plc:a", "alpha", false, Some(exp)),
+                "3ka",
+            )
+            .await
+            .unwrap(),
+        );
+        // The event's dates moved: re-issued now, after the unlike happened.
+        let later = exp + chrono::Days::new(1);
+        seqs.extend(
+            apply(
+                &keypair,
+                &mut tx,
+                &src,
+                "did:plc:a",
+                "alpha",
+                Some(later),
+                "3ka",
+            )
+            .await
+            .unwrap(),
+        );
+        assert_eq!(
+            apply(
+                &keypair,
+                &mut tx,
+                &src,
+                "did:plc:a",
+                "alpha",
+                Some(later),
+                "3ka"
+            )
+            .await
+            .unwrap(),
+            None
+        );
+        seqs.extend(
+            emit(
+                &keypair,
+                &mut tx,
+                &label_at(unliked_at, "did:plc:a", "alpha", true, None),
+                "3ka",
+            )
+            .await
+            .unwrap(),
+        );
+        assert!(current(&mut tx, "did:plc:a").await.unwrap().is_empty());
+        assert_eq!(seqs.len(), 3);
+        assert_eq!(seqs[2] - seqs[0], 2);
+    }
 }
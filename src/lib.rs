@@ -1,5 +1,22 @@
+pub mod accounts;
+pub mod appview;
+pub mod backfill;
 pub mod con_posts;
+pub mod did_doc;
+pub mod event_posts;
+pub mod events;
+pub mod handles;
 pub mod jetstream;
 pub mod keydates_announce;
+pub mod label_cap;
+pub mod label_policy;
+pub mod label_strings;
 pub mod labels;
+pub mod like_labels;
+pub mod opt_out;
 pub mod roman;
+pub mod sync_journal;
+pub mod sync_plan;
+#[cfg(test)]
+mod test_db;
+pub mod xrpc;
@@ -0,0 +1,449 @@
+//! Which labels a like of an event post gives: the edition's own label
+//! (expiring with the event) and/or a label for the event's series, which
+//! has no expiry and stays on as long as the liker likes any edition of it.
+//!
+//! Every label a like gives is recorded in `like_labels`, so an unlike takes
+//! off exactly what no other like of the same account still gives.
+
+/// A label a like can give; the set is `like_labels` in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Edition,
+    Series,
+}
+
+/// The label val of a series, e.g. `series-anthrocon`. Prefixed so it can't
+/// collide with an edition's.
+pub fn series_label(series_id: &str) -> String {
+    format!("series-{}", crate::events::id_to_label(series_id))
+}
+
+pub fn is_series(val: &str) -> bool {
+    val.starts_with("series-")
+}
+
+/// The series name for its label definition: the edition's name without a
+/// trailing year. The year needn't follow a space (`…ファーリーズ2025`), only
+/// something other than a digit.
+pub fn series_name(edition_name: &str) -> String {
+    static YEAR_RE: std::sync::LazyLock<regex::Regex> =
+        std::sync::LazyLock::new(|| regex::Regex::new(r"(\D)\s*\d{4}$").unwrap());
+    YEAR_RE
+        .replace(edition_name.trim(), "${1}")
+        .trim_end()
+        .to_string()
+}
+
+/// The labels, as `(val, exp)`, a like of `assoc_event`'s post gives.
+pub fn for_event(
+    kinds: &[Kind],
+    assoc_event: &crate::events::AssociatedEvent,
+) -> Vec<(String, Option<chrono::DateTime<chrono::Utc>>)> {
+    kinds
+        .iter()
+        .filter_map(|kind| match kind {
+            Kind::Edition => Some((
+                assoc_event.label_id.clone(),
+                Some(assoc_event.event.end_time() + crate::events::EXPIRY_DATE_GRACE_PERIOD),
+            )),
+            Kind::Series => Some((series_label(assoc_event.event.series_id.as_ref()?), None)),
+        })
+        .collect()
+}
+
+/// Record that `uri`'s like `like_rkey` gives it `val`. Returns whether `val`
+/// is to be applied: no other like already gives it, or one does but the
+/// label isn't on (taken off by an opt-out or by hand since). A label a
+/// moderator put on stays theirs, so this like's unlike leaves it.
+pub async fn record(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+    like_rkey: &str,
+    val: &str,
+) -> Result<bool, sqlx::Error> {
+    let given = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM current_labels c
+            WHERE uri = $1 AND val = $2 AND NOT neg AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+            AND (
+                c.like_rkey = $4
+                OR EXISTS (
+                    SELECT 1 FROM like_labels
+                    WHERE uri = $1 AND val = $2 AND like_rkey <> $3
+                )
+            )
+        ) AS "given!"
+        "#,
+        uri,
+        val,
+        like_rkey,
+        crate::labels::ADMIN_LIKE_RKEY,
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO like_labels (uri, like_rkey, val) VALUES ($1, $2, $3)
+        ON CONFLICT DO NOTHING
+        "#,
+        uri,
+        like_rkey,
+        val,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(!given)
+}
+
+/// Forget what `uri`'s like `like_rkey` gave, returning the labels to
+/// negate: those it gave that no other like of `uri` still gives, and that
+/// are on on its behalf. A label applied by hand, or held by another like,
+/// stays. A label this like held that another still gives is handed over to
+/// that one, whose unlike then takes it off. A like from before
+/// `like_labels` was recorded falls back to the label log, which knows the
+/// one (edition) val it gave; series labels are always recorded.
+pub async fn unlike(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+    like_rkey: &str,
+) -> Result<Vec<String>, sqlx::Error> {
+    let gave = sqlx::query_scalar!(
+        r#"
+        DELETE FROM like_labels WHERE uri = $1 AND like_rkey = $2
+        RETURNING val
+        "#,
+        uri,
+        like_rkey,
+    )
+    .fetch_all(&mut **tx)
+    .await?;
+
+    let gone = if gave.is_empty() {
+        sqlx::query_scalar!(
+            r#"
+            SELECT val FROM labels
+            WHERE like_rkey = $1 AND uri = $2 AND NOT neg AND val NOT LIKE 'series-%'
+            ORDER BY seq DESC
+            LIMIT 1
+            "#,
+            like_rkey,
+            uri
+        )
+        .fetch_optional(&mut **tx)
+        .await?
+        .into_iter()
+        .collect()
+    } else {
+        let still_given = sqlx::query_scalar!(
+            r#"
+            SELECT DISTINCT val FROM like_labels WHERE uri = $1 AND val = ANY($2)
+            "#,
+            uri,
+            &gave,
+        )
+        .fetch_all(&mut **tx)
+        .await?;
+        sqlx::query!(
+            r#"
+            UPDATE current_labels c
+            SET like_rkey = (
+                SELECT min(l.like_rkey) FROM like_labels l
+                WHERE l.uri = c.uri AND l.val = c.val
+            )
+            WHERE uri = $1 AND like_rkey = $2 AND val = ANY($3)
+            "#,
+            uri,
+            like_rkey,
+            &still_given,
+        )
+        .execute(&mut **tx)
+        .await?;
+        gave.into_iter()
+            .filter(|val| !still_given.contains(val))
+            .collect::<Vec<_>>()
+    };
+
+    sqlx::query_scalar!(
+        r#"
+        SELECT val FROM current_labels
+        WHERE uri = $1 AND like_rkey = $2 AND val = ANY($3)
+        AND NOT neg AND (exp IS NULL OR exp > CURRENT_TIMESTAMP)
+        ORDER BY val
+        "#,
+        uri,
+        like_rkey,
+        &gone,
+    )
+    .fetch_all(&mut **tx)
+    .await
+}
+
+/// Forget that `uri`'s like `like_rkey` gives `val`: the like is gone, and
+/// another holds the label now.
+pub async fn forget(
+    tx: &mut sqlx::PgTransaction<'_>,
+    uri: &str,
+    like_rkey: &str,
+    val: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM like_labels WHERE uri = $1 AND like_rkey = $2 AND val = $3
+        "#,
+        uri,
+        like_rkey,
+        val,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Move what likes give from `old_val` to `new_val`, for an event whose
+/// label was renamed. A like that already gives `new_val` (a replay since
+/// the rename) keeps that and drops `old_val`.
+pub async fn rename(
+    tx: &mut sqlx::PgTransaction<'_>,
+    old_val: &str,
+    new_val: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM like_labels o
+        WHERE val = $1 AND EXISTS (
+            SELECT 1 FROM like_labels n
+            WHERE n.uri = o.uri AND n.like_rkey = o.like_rkey AND n.val = $2
+        )
+        "#,
+        old_val,
+        new_val,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE like_labels SET val = $2 WHERE val = $1
+        "#,
+        old_val,
+        new_val,
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn series_labels_and_names() {
+        assert_eq!(series_label("Anthrocon"), "series-anthrocon");
+        assert_eq!(series_label("fc"), "series-fc");
+        assert!(is_series(&series_label("fc")));
+        assert!(!is_series("fc-mmxxv"));
+        assert_eq!(series_name("Anthrocon 2025"), "Anthrocon");
+        assert_eq!(series_name("Eurofurence 29"), "Eurofurence 29");
+        assert_eq!(series_name("Midwest FurFest"), "Midwest FurFest");
+        assert_eq!(
+            series_name("ジャパンミーティングオブファーリーズ2025"),
+            "ジャパンミーティングオブファーリーズ"
+        );
+        assert_eq!(series_name("Con 12025"), "Con 12025");
+        assert_eq!(series_name("2025"), "2025");
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // Two editions liked: unliking one leaves the series label to the other.
+    #[tokio::test]
+    #[ignore]
+    async fn series_outlives_one_unlike() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        for (like_rkey, val, first) in [
+            ("3k2024", "ac-mmxxiv", true),
+            ("3k2024", "series-ac", true),
+            ("3k2025", "ac-mmxxv", true),
+            ("3k2025", "series-ac", false),
+            // replayed
+            ("3k2024", "series-ac", false),
+        ] {
+            assert_eq!(
+                record(&mut tx, "did:plc:a", like_rkey, val).await.unwrap(),
+                first,
+                "{like_rkey} {val}"
+            );
+            if first {
+                crate::labels::apply(&keypair, &mut tx, &src, "did:plc:a", val, None, like_rkey)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(
+            unlike(&mut tx, "did:plc:a", "3k2024").await.unwrap(),
+            vec!["ac-mmxxiv"]
+        );
+        assert_eq!(
+            unlike(&mut tx, "did:plc:a", "3k2025").await.unwrap(),
+            vec!["ac-mmxxv", "series-ac"]
+        );
+        // Replayed: nothing recorded is left, so it falls back to the log
+        // (and the label is still on, as nothing here negated it).
+        assert_eq!(
+            unlike(&mut tx, "did:plc:a", "3k2025").await.unwrap(),
+            vec!["ac-mmxxv"]
+        );
+    }
+
+    // Labels taken off by an opt-out come back with the next like once opted
+    // back in, even though the earlier likes are still recorded.
+    #[tokio::test]
+    #[ignore]
+    async fn series_returns_after_opt_in() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        for val in ["ac-mmxxiv", "series-ac"] {
+            assert!(record(&mut tx, "did:plc:a", "3k2024", val).await.unwrap());
+            crate::labels::apply(&keypair, &mut tx, &src, "did:plc:a", val, None, "3k2024")
+                .await
+                .unwrap();
+        }
+
+        crate::opt_out::opt_out(&keypair, &mut tx, &src, "did:plc:a", "3kblock")
+            .await
+            .unwrap();
+        assert!(crate::opt_out::opt_in(&mut tx, "did:plc:a", "3kblock")
+            .await
+            .unwrap());
+
+        assert!(record(&mut tx, "did:plc:a", "3k2025", "ac-mmxxv")
+            .await
+            .unwrap());
+        assert!(record(&mut tx, "did:plc:a", "3k2025", "series-ac")
+            .await
+            .unwrap());
+    }
+
+    // An event renamed after the like: the unlike takes off the new label.
+    #[tokio::test]
+    #[ignore]
+    async fn unlike_after_rename() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        assert!(record(&mut tx, "did:plc:a", "3k2025", "ac-mmxxv")
+            .await
+            .unwrap());
+        crate::labels::apply(
+            &keypair,
+            &mut tx,
+            &src,
+            "did:plc:a",
+            "ac-mmxxv",
+            None,
+            "3k2025",
+        )
+        .await
+        .unwrap();
+        // replayed after the rename
+        assert!(record(&mut tx, "did:plc:b", "3kb", "ac-mmxxv")
+            .await
+            .unwrap());
+        assert!(record(&mut tx, "did:plc:b", "3kb", "anthrocon-mmxxv")
+            .await
+            .unwrap());
+
+        // What the label sync does for a renamed event.
+        for current in crate::labels::current(&mut tx, "did:plc:a").await.unwrap() {
+            crate::labels::negate(&keypair, &mut tx, &src, &current)
+                .await
+                .unwrap();
+            crate::labels::apply(
+                &keypair,
+                &mut tx,
+                &src,
+                &current.uri,
+                "anthrocon-mmxxv",
+                None,
+                &current.like_rkey,
+            )
+            .await
+            .unwrap();
+        }
+        rename(&mut tx, "ac-mmxxv", "anthrocon-mmxxv")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            unlike(&mut tx, "did:plc:a", "3k2025").await.unwrap(),
+            vec!["anthrocon-mmxxv"]
+        );
+        let vals: Vec<String> =
+            sqlx::query_scalar("SELECT val FROM like_labels WHERE uri = 'did:plc:b'")
+                .fetch_all(&mut *tx)
+                .await
+                .unwrap();
+        assert_eq!(vals, vec!["anthrocon-mmxxv"]);
+    }
+
+    // A label put on by hand outlives the unlike of a like that also gives
+    // it; one held by another like of the same event outlives it too.
+    #[tokio::test]
+    #[ignore]
+    async fn unlike_leaves_labels_it_does_not_hold() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let mut tx = db_pool.begin().await.unwrap();
+        crate::labels::apply(
+            &keypair,
+            &mut tx,
+            &src,
+            "did:plc:a",
+            "ac-mmxxv",
+            None,
+            crate::labels::ADMIN_LIKE_RKEY,
+        )
+        .await
+        .unwrap();
+        assert!(!record(&mut tx, "did:plc:a", "3k2025", "ac-mmxxv")
+            .await
+            .unwrap());
+        assert!(unlike(&mut tx, "did:plc:a", "3k2025")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            crate::labels::current(&mut tx, "did:plc:a").await.unwrap()[0].like_rkey,
+            crate::labels::ADMIN_LIKE_RKEY
+        );
+
+        // From before like_labels: the log says 3kold gave it, but 3knew
+        // holds it now.
+        for like_rkey in ["3kold", "3knew"] {
+            crate::labels::apply(
+                &keypair,
+                &mut tx,
+                &src,
+                "did:plc:b",
+                "ac-mmxxv",
+                None,
+                like_rkey,
+            )
+            .await
+            .unwrap();
+        }
+        assert!(unlike(&mut tx, "did:plc:b", "3kold")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use atrium_api::types::{Collection as _, TryFromUnknown as _, TryIntoUnknown as _};
 use axum::response::IntoResponse as _;
 use bsky_event_ingester::*;
+use events::{fetch_events, AssociatedEvent, EXPIRY_DATE_GRACE_PERIOD};
 use futures::StreamExt as _;
 use sqlx::Acquire as _;
 
@@ -10,6 +11,13 @@ struct Config {
     bsky_password: String,
     bsky_endpoint: String,
     ui_endpoint: String,
+    // The post new events get: "plain" (the name, linked to ui_endpoint) or
+    // "rich" (with dates, venue, a series #tag and a link card).
+    post_template: event_posts::Template,
+    // Posts that no longer match their event (or post_template): "off",
+    // "dry_run" (log what would change) or "apply" (update them in place).
+    // See src/event_posts.rs.
+    post_updates: event_posts::Updates,
     // Dial order for the Jetstream firehose; see jetstream::DEFAULT_ENDPOINTS.
     // The consumer fails over to the next entry after repeated short-lived
     // connections. `jetstream_endpoint` (singular, the pre-failover key) is a
@@ -23,8 +31,38 @@ struct Config {
     events_url: String,
     postgres_url: String,
     keypair_path: String,
+    // Where the labeler DID is resolved at startup to check that keypair_path
+    // holds the published label key, and what to do when it doesn't.
+    plc_url: url::Url,
+    on_label_key_mismatch: OnLabelKeyMismatch,
+    // Asked whether a reactivated account's likes still exist; see
+    // src/accounts.rs.
+    appview_url: url::Url,
+    // Most con labels on one account at a time; unset = no cap. Likes over
+    // it are deferred. See src/label_cap.rs.
+    max_labels_per_user: Option<usize>,
+    deferred_label_promote_every_secs: u64,
+    // What a like of an event post labels its liker with: "edition" (the
+    // event itself) and/or "series" (any edition of it, no expiry). Only
+    // editions count towards max_labels_per_user. See src/like_labels.rs.
+    like_labels: Vec<like_labels::Kind>,
+    // Per-event/per-series label definition policy (blurs, severity,
+    // default_setting, adult_only) over the feed's; unset = feed only. Re-read
+    // on every sync. See src/label_policy.rs.
+    label_policies_path: Option<std::path::PathBuf>,
+    // Catch up on likes through the AppView at startup (also on demand with
+    // POST /backfill). See src/backfill.rs.
+    backfill_on_startup: bool,
+    // Compare every event's likers with its labels; unset = only on demand
+    // (POST /reconcile[?fix=true]). Reports only, unless reconcile_fix.
+    reconcile_every_secs: Option<u64>,
+    reconcile_fix: bool,
     ingester_bind: std::net::SocketAddr,
     commit_firehose_cursor_every_secs: u64,
+    // Label log compaction: see labels::compact. Superseded and expired rows
+    // are deleted once they are label_compaction_grace_secs old.
+    label_compaction_every_secs: u64,
+    label_compaction_grace_secs: u64,
     // Key-date detection: unset = feature off. See src/con_posts.rs.
     con_posts_spool_dir: Option<std::path::PathBuf>,
     keydates_worker_cmd: Option<String>,
@@ -47,16 +85,39 @@ impl std::fmt::Debug for Config {
             .field("bsky_password", &"<redacted>")
             .field("bsky_endpoint", &self.bsky_endpoint)
             .field("ui_endpoint", &self.ui_endpoint)
+            .field("post_template", &self.post_template)
+            .field("post_updates", &self.post_updates)
             .field("jetstream_endpoints", &self.jetstream_endpoints)
             .field("jetstream_endpoint", &self.jetstream_endpoint)
             .field("events_url", &self.events_url)
             .field("postgres_url", &"<redacted>")
             .field("keypair_path", &self.keypair_path)
+            .field("plc_url", &self.plc_url)
+            .field("on_label_key_mismatch", &self.on_label_key_mismatch)
+            .field("appview_url", &self.appview_url)
+            .field("max_labels_per_user", &self.max_labels_per_user)
+            .field(
+                "deferred_label_promote_every_secs",
+                &self.deferred_label_promote_every_secs,
+            )
+            .field("like_labels", &self.like_labels)
+            .field("label_policies_path", &self.label_policies_path)
+            .field("backfill_on_startup", &self.backfill_on_startup)
+            .field("reconcile_every_secs", &self.reconcile_every_secs)
+            .field("reconcile_fix", &self.reconcile_fix)
             .field("ingester_bind", &self.ingester_bind)
             .field(
                 "commit_firehose_cursor_every_secs",
                 &self.commit_firehose_cursor_every_secs,
             )
+            .field(
+                "label_compaction_every_secs",
+                &self.label_compaction_every_secs,
+            )
+            .field(
+                "label_compaction_grace_secs",
+                &self.label_compaction_grace_secs,
+            )
             .field("con_posts_spool_dir", &self.con_posts_spool_dir)
             .field("keydates_worker_cmd", &self.keydates_worker_cmd)
             .field("con_post_debounce_secs", &self.con_post_debounce_secs)
@@ -72,21 +133,20 @@ impl std::fmt::Debug for Config {
     }
 }
 
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OnLabelKeyMismatch {
+    /// Exit: nothing we sign would verify.
+    Refuse,
+    /// Keep labeling, loudly. For when the DID document update is in flight.
+    Degraded,
+}
+
 struct EventsState {
     rkeys_to_ids: std::collections::HashMap<atrium_api::types::string::RecordKey, String>,
     events: std::collections::HashMap<String, AssociatedEvent>,
 }
 
-fn id_to_label(s: &str) -> String {
-    static NUMBERS_RE: std::sync::LazyLock<regex::Regex> =
-        std::sync::LazyLock::new(|| regex::Regex::new(r"\d+").unwrap());
-    NUMBERS_RE
-        .replace_all(&s.to_ascii_lowercase(), |caps: &regex::Captures| {
-            roman::to_roman(caps[0].parse().unwrap()).to_ascii_lowercase()
-        })
-        .to_string()
-}
-
 async fn list_all_records(
     agent: &atrium_api::agent::Agent<
         atrium_api::agent::atp_agent::CredentialSession<
@@ -133,101 +193,65 @@ async fn list_all_records(
     Ok(records)
 }
 
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct BlueskyRef {
-    did: String,
-    #[allow(dead_code)]
-    handle: Option<String>,
-}
-
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct IngestedEvent {
-    id: String,
-    name: String,
-    venue: String,
-    locale: String,
-    address: Option<String>,
-    start_date: chrono::NaiveDate,
-    end_date: chrono::NaiveDate,
-    timezone: Option<String>,
-    #[serde(default)]
-    series_id: Option<String>,
-    #[serde(default)]
-    bluesky: Option<BlueskyRef>,
-    #[serde(default)]
-    key_dates: Option<serde_json::Value>,
-}
+const EXTRA_DATA_POST_RKEY: &str = "fbl_postRkey";
+const EXTRA_DATA_EVENT_ID: &str = "fbl_eventId";
+/// Set on series label definitions, whose event id (and post) is null.
+const EXTRA_DATA_SERIES_ID: &str = "fbl_seriesId";
 
 #[derive(Debug)]
-struct AssociatedEvent {
-    event: IngestedEvent,
+struct OldEvent {
     rkey: Option<atrium_api::types::string::RecordKey>,
-    label_id: String,
+    id: String,
 }
 
-impl IngestedEvent {
-    fn end_time(&self) -> chrono::DateTime<chrono::Utc> {
-        let date = self.end_date + chrono::Days::new(1);
-        let timezone = self
-            .timezone
-            .as_ref()
-            .and_then(|tz| tz.parse().ok())
-            .unwrap_or(chrono_tz::UTC);
-
-        date.and_time(chrono::NaiveTime::from_hms_opt(0, 0, 0).unwrap())
-            .and_local_timezone(timezone)
-            .earliest()
-            .or_else(|| {
-                self.end_date
-                    .and_time(chrono::NaiveTime::from_hms_opt(1, 0, 0).unwrap())
-                    .and_local_timezone(timezone)
-                    .earliest()
-            })
-            .unwrap()
-            .to_utc()
-    }
-}
+/// Old label identifiers whose event was renamed upstream, to the event's
+/// new id: the old event id is gone from the feed, but some event lists it in
+/// `previousIds`.
+fn renamed_events(
+    old_events: &std::collections::HashMap<String, OldEvent>,
+    events: &std::collections::HashMap<String, AssociatedEvent>,
+) -> std::collections::HashMap<String, String> {
+    let renamed_from = events
+        .values()
+        .flat_map(|assoc_event| {
+            assoc_event
+                .event
+                .previous_ids
+                .iter()
+                .map(|previous_id| (previous_id, &assoc_event.event.id))
+        })
+        .collect::<std::collections::HashMap<_, _>>();
 
-async fn fetch_events(
-    reqwest_client: &reqwest::Client,
-    events_url: &str,
-) -> Result<std::collections::HashMap<String, AssociatedEvent>, anyhow::Error> {
-    reqwest_client
-        .get(events_url)
-        .send()
-        .await?
-        .error_for_status()?
-        .text()
-        .await?
-        .lines()
-        .map(|line| {
-            let event = serde_json::from_str::<IngestedEvent>(line)?;
-            Ok::<_, anyhow::Error>((
-                event.id.clone(),
-                AssociatedEvent {
-                    rkey: None,
-                    label_id: id_to_label(&event.id),
-                    event,
-                },
-            ))
+    old_events
+        .iter()
+        .filter(|(_, oe)| !events.contains_key(&oe.id))
+        .flat_map(|(label_id, oe)| {
+            renamed_from
+                .get(&oe.id)
+                .map(|id| (label_id.clone(), (*id).clone()))
         })
-        .collect::<Result<_, _>>()
+        .collect()
 }
 
-const EXTRA_DATA_POST_RKEY: &str = "fbl_postRkey";
-const EXTRA_DATA_EVENT_ID: &str = "fbl_eventId";
-
-#[derive(Debug)]
-struct OldEvent {
-    rkey: Option<atrium_api::types::string::RecordKey>,
-    id: String,
+/// Old label identifiers whose post now belongs to an event under another
+/// label identifier, to that event's id: what `renamed_events` misses when
+/// the post is kept but the label changes with no `previousIds` to go on.
+/// `old` is the events state as of the last sync.
+fn relabeled_posts(
+    old: &EventsState,
+    events: &std::collections::HashMap<String, AssociatedEvent>,
+) -> std::collections::HashMap<String, String> {
+    events
+        .iter()
+        .filter_map(|(id, assoc_event)| {
+            let old_id = old.rkeys_to_ids.get(assoc_event.rkey.as_ref()?)?;
+            let old_label_id = &old.events.get(old_id)?.label_id;
+            (*old_label_id != assoc_event.label_id).then(|| (old_label_id.clone(), id.clone()))
+        })
+        .collect()
 }
 
-const EXPIRY_DATE_GRACE_PERIOD: chrono::Days = chrono::Days::new(7);
-
-async fn fetch_old_events(
+async fn fetch_service_record(
     did: &atrium_api::types::string::Did,
     agent: &atrium_api::agent::Agent<
         atrium_api::agent::atp_agent::CredentialSession<
@@ -235,8 +259,8 @@ async fn fetch_old_events(
             atrium_xrpc_client::reqwest::ReqwestClient,
         >,
     >,
-) -> Result<Option<std::collections::HashMap<String, OldEvent>>, anyhow::Error> {
-    let Some(record) = match agent
+) -> Result<Option<atrium_api::app::bsky::labeler::service::Record>, anyhow::Error> {
+    Ok(match agent
         .api
         .com
         .atproto
@@ -266,11 +290,13 @@ async fn fetch_old_events(
     }
     .and_then(|record| {
         atrium_api::app::bsky::labeler::service::Record::try_from_unknown(record.data.value).ok()
-    }) else {
-        return Ok(None);
-    };
+    }))
+}
 
-    Ok(record
+fn old_events(
+    record: &atrium_api::app::bsky::labeler::service::Record,
+) -> std::collections::HashMap<String, OldEvent> {
+    record
         .policies
         .data
         .label_value_definitions
@@ -301,11 +327,140 @@ async fn fetch_old_events(
                     Some((v.identifier.clone(), OldEvent { rkey, id }))
                 })
                 .collect::<std::collections::HashMap<_, _>>()
-        }))
+        })
+        .unwrap_or_default()
+}
+
+/// The CID of the repo's current commit.
+async fn latest_commit(
+    did: &atrium_api::types::string::Did,
+    agent: &atrium_api::agent::Agent<
+        atrium_api::agent::atp_agent::CredentialSession<
+            atrium_api::agent::atp_agent::store::MemorySessionStore,
+            atrium_xrpc_client::reqwest::ReqwestClient,
+        >,
+    >,
+) -> Result<String, anyhow::Error> {
+    Ok(agent
+        .api
+        .com
+        .atproto
+        .sync
+        .get_latest_commit(
+            atrium_api::com::atproto::sync::get_latest_commit::ParametersData { did: did.clone() }
+                .into(),
+        )
+        .await?
+        .data
+        .cid
+        .as_ref()
+        .to_string())
+}
+
+/// Apply `journal`'s chunks from where it got to, each on top of the commit
+/// the one before left. Returns false, having abandoned the journal, if the
+/// repo is no longer at that commit.
+async fn apply_journal(
+    did: &atrium_api::types::string::Did,
+    agent: &atrium_api::agent::Agent<
+        atrium_api::agent::atp_agent::CredentialSession<
+            atrium_api::agent::atp_agent::store::MemorySessionStore,
+            atrium_xrpc_client::reqwest::ReqwestClient,
+        >,
+    >,
+    db_conn: &mut sqlx::PgConnection,
+    journal: &mut sync_journal::Journal,
+) -> Result<bool, anyhow::Error> {
+    while journal.applied < journal.plan.chunks.len() {
+        let output = match agent
+            .api
+            .com
+            .atproto
+            .repo
+            .apply_writes(
+                atrium_api::com::atproto::repo::apply_writes::InputData {
+                    repo: did.clone().into(),
+                    swap_commit: Some(journal.commit_cid.parse()?),
+                    validate: Some(true),
+                    writes: journal.plan.chunks[journal.applied].clone(),
+                }
+                .into(),
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(atrium_api::xrpc::Error::XrpcResponse(atrium_api::xrpc::error::XrpcError {
+                error:
+                    Some(atrium_api::xrpc::error::XrpcErrorKind::Custom(
+                        atrium_api::com::atproto::repo::apply_writes::Error::InvalidSwap(..),
+                    )),
+                ..
+            })) => {
+                log::warn!(
+                    "repo is no longer at {}: abandoning sync journal {} after {}/{} chunk(s)",
+                    journal.commit_cid,
+                    journal.id,
+                    journal.applied,
+                    journal.plan.chunks.len()
+                );
+                sync_journal::abandon(db_conn, journal).await?;
+                return Ok(false);
+            }
+            Err(err) => {
+                return Err(err.into());
+            }
+        };
+
+        journal.applied += 1;
+        journal.commit_cid = match output.data.commit {
+            Some(commit) => commit.cid.as_ref().to_string(),
+            None => latest_commit(did, agent).await?,
+        };
+        sync_journal::advance(db_conn, journal).await?;
+        log::info!(
+            "applied chunk {}/{} of sync journal {}",
+            journal.applied,
+            journal.plan.chunks.len(),
+            journal.id
+        );
+    }
+    Ok(true)
+}
+
+/// Con accounts to watch for key-date posts: did -> series id.
+fn watchlist_of(
+    events: &std::collections::HashMap<String, AssociatedEvent>,
+) -> std::collections::HashMap<String, String> {
+    events
+        .values()
+        .flat_map(|assoc_event| {
+            Some((
+                assoc_event.event.bluesky.as_ref()?.did.clone(),
+                assoc_event.event.series_id.as_ref()?.clone(),
+            ))
+        })
+        .collect()
+}
+
+/// What a sync works out before it writes anything.
+struct Planned {
+    events: std::collections::HashMap<String, AssociatedEvent>,
+    commit_cid: String,
+    writes: Vec<atrium_api::com::atproto::repo::apply_writes::InputWritesItem>,
+    renamed: std::collections::HashMap<String, String>,
+    removed_label_ids: Vec<String>,
+    // The repo as found, for sync_plan.
+    posts:
+        std::collections::HashMap<atrium_api::types::string::RecordKey, atrium_api::types::Unknown>,
+    post_events: std::collections::HashMap<String, String>,
+    old_definitions: Vec<atrium_api::com::atproto::label::defs::LabelValueDefinition>,
 }
 
+/// Diff the events feed against the repo: the writes that bring the repo up
+/// to date, and the label work that follows them. `carried` is label work
+/// handed on by an unfinished sync. Writes nothing.
 #[allow(clippy::too_many_arguments)]
-async fn sync_labels(
+async fn plan_sync(
     reqwest_client: &reqwest::Client,
     events_url: &str,
     ui_endpoint: &str,
@@ -316,25 +471,36 @@ async fn sync_labels(
             atrium_xrpc_client::reqwest::ReqwestClient,
         >,
     >,
-    events_state: std::sync::Arc<tokio::sync::Mutex<EventsState>>,
-    watchlist: con_posts::Watchlist,
-    announcer: Option<&keydates_announce::Announcer>,
-) -> Result<(), anyhow::Error> {
-    // Lock the entire events state while labels are syncing.
-    //
-    // This means that we hold the mutex while events are being created, such that any likes on those posts must wait until the mutex is unlocked.
-    // This ensures that we don't get into a state where if someone likes a post but we haven't saved it into the events state yet we end up missing their like.
-    let mut events_state = events_state.lock().await;
-
+    carried: sync_journal::Plan,
+    like_label_kinds: &[like_labels::Kind],
+    label_policies_path: Option<&std::path::Path>,
+    post_template: event_posts::Template,
+    post_updates: event_posts::Updates,
+) -> Result<Planned, anyhow::Error> {
     let now = chrono::Utc::now();
 
     let mut events = fetch_events(reqwest_client, events_url).await?;
+    let label_policies = match label_policies_path {
+        Some(path) => label_policy::Overrides::load(path)?,
+        None => Default::default(),
+    };
+    let unmatched = label_policies.unmatched(events.values().map(|assoc_event| &assoc_event.event));
+    if !unmatched.is_empty() {
+        log::warn!("label policy overrides for no event or series in the feed: {unmatched:?}");
+    }
+
+    let commit_cid = latest_commit(did, agent).await?;
 
     let mut writes = vec![];
 
-    let mut old_events = fetch_old_events(did, agent).await?.unwrap_or_default();
+    let service_record = fetch_service_record(did, agent).await?;
+    let old_definitions = service_record
+        .as_ref()
+        .and_then(|record| record.policies.label_value_definitions.clone())
+        .unwrap_or_default();
+    let mut old_events = service_record.as_ref().map(old_events).unwrap_or_default();
 
-    let record_rkeys = list_all_records(agent, did)
+    let posts = list_all_records(agent, did)
         .await?
         .into_iter()
         .map(|record| {
@@ -347,13 +513,31 @@ async fn sync_labels(
                 unreachable!();
             };
 
-            atrium_api::types::string::RecordKey::new(rkey.to_string()).unwrap()
+            (
+                atrium_api::types::string::RecordKey::new(rkey.to_string()).unwrap(),
+                record.data.value,
+            )
         })
-        .collect::<std::collections::HashSet<atrium_api::types::string::RecordKey>>();
+        .collect::<std::collections::HashMap<atrium_api::types::string::RecordKey, _>>();
+
+    // Labels of events that left the feed are withdrawn once their posts are
+    // gone, rather than left to run out at `exp`; those of renamed events
+    // move to the new identifier.
+    let mut renamed = renamed_events(&old_events, &events);
+    for (old_label_id, id) in carried.renamed {
+        if events.contains_key(&id) {
+            renamed.entry(old_label_id).or_insert(id);
+        }
+    }
+    let mut removed_label_ids = old_events
+        .iter()
+        .filter(|(label_id, oe)| !events.contains_key(&oe.id) && !renamed.contains_key(*label_id))
+        .map(|(label_id, _)| label_id.clone())
+        .collect::<Vec<_>>();
 
     old_events.retain(|_, oe| {
         if let Some(rkey) = oe.rkey.as_ref() {
-            if !record_rkeys.contains(rkey) {
+            if !posts.contains_key(rkey) {
                 log::info!("could not find {}, will recreate", rkey.as_str());
                 return false;
             }
@@ -361,7 +545,12 @@ async fn sync_labels(
         true
     });
 
-    // Delete old events if we don't see them in our retrieved events.
+    // Delete old events if we don't see them in our retrieved events. Events
+    // still under their own id go first, so a renamed event can only take
+    // over a post nobody else kept.
+    let mut post_events = std::collections::HashMap::new();
+    let mut old_events = old_events.into_iter().collect::<Vec<_>>();
+    old_events.sort_by_key(|(_, oe)| !events.contains_key(&oe.id));
     for (label_id, oe) in old_events.into_iter() {
         if let Some(event) = events.get_mut(&oe.id) {
             event.rkey = oe.rkey.clone();
@@ -369,7 +558,20 @@ async fn sync_labels(
             continue;
         }
 
+        if let Some(event) = renamed.get(&label_id).and_then(|id| events.get_mut(id)) {
+            if event.rkey.is_none() {
+                log::info!(
+                    "{} was renamed to {}, keeping its post",
+                    oe.id,
+                    event.event.id
+                );
+                event.rkey = oe.rkey.clone();
+                continue;
+            }
+        }
+
         if let Some(rkey) = oe.rkey {
+            post_events.insert(rkey.as_str().to_string(), oe.id.clone());
             writes.push(
                 atrium_api::com::atproto::repo::apply_writes::InputWritesItem::Delete(Box::new(
                     atrium_api::com::atproto::repo::apply_writes::DeleteData {
@@ -391,6 +593,81 @@ async fn sync_labels(
         }
     }
 
+    // A carried-over removal stands unless the label is in use again.
+    let carried_removed = carried
+        .removed_label_ids
+        .into_iter()
+        .filter(|label_id| {
+            !removed_label_ids.contains(label_id)
+                && !renamed.contains_key(label_id)
+                && !events
+                    .values()
+                    .any(|assoc_event| assoc_event.label_id == *label_id)
+        })
+        .collect::<Vec<_>>();
+    removed_label_ids.extend(carried_removed);
+
+    // Bring posts kept from before up to date with their event.
+    if post_updates != event_posts::Updates::Off {
+        let mut out_of_date = 0;
+        for assoc_event in events.values() {
+            let Some(rkey) = assoc_event.rkey.as_ref() else {
+                continue;
+            };
+            let Some(existing) = posts.get(rkey).cloned().and_then(|value| {
+                atrium_api::app::bsky::feed::post::Record::try_from_unknown(value).ok()
+            }) else {
+                continue;
+            };
+            let wanted = event_posts::record(
+                post_template,
+                ui_endpoint,
+                &assoc_event.event,
+                existing.created_at.as_ref().to_utc(),
+            );
+            let changes = event_posts::changes(&existing, &wanted);
+            if changes.is_empty() {
+                continue;
+            }
+            out_of_date += 1;
+            if post_updates == event_posts::Updates::DryRun {
+                log::info!(
+                    "would update post {} for {} ({changes:?}): {:?} -> {:?}",
+                    rkey.as_str(),
+                    assoc_event.event.id,
+                    existing.text,
+                    wanted.text
+                );
+                continue;
+            }
+            log::info!(
+                "updating post {} for {} ({changes:?})",
+                rkey.as_str(),
+                assoc_event.event.id
+            );
+            writes.push(
+                atrium_api::com::atproto::repo::apply_writes::InputWritesItem::Update(Box::new(
+                    atrium_api::com::atproto::repo::apply_writes::UpdateData {
+                        collection: atrium_api::app::bsky::feed::Post::nsid(),
+                        rkey: rkey.clone(),
+                        value: wanted.try_into_unknown().unwrap(),
+                    }
+                    .into(),
+                )),
+            );
+        }
+        if out_of_date > 0 {
+            log::info!(
+                "{out_of_date} post(s) out of date{}",
+                if post_updates == event_posts::Updates::DryRun {
+                    " (dry run: post_updates = \"apply\" to update them)"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+
     // Create new events.
     let mut sorted_events = events.iter_mut().collect::<Vec<_>>();
     sorted_events.sort_by_key(|(_, assoc_event)| {
@@ -417,46 +694,8 @@ async fn sync_labels(
             assoc_event.rkey = Some(rkey.clone());
 
             {
-                let text = assoc_event.event.name.to_string();
-
-                let record: atrium_api::app::bsky::feed::post::Record =
-                    atrium_api::app::bsky::feed::post::RecordData {
-                        created_at: atrium_api::types::string::Datetime::new(
-                            created_at.fixed_offset(),
-                        ),
-                        embed: None,
-                        entities: None,
-                        labels: None,
-                        langs: Some(vec![atrium_api::types::string::Language::new(
-                            "en".to_string(),
-                        )
-                        .unwrap()]),
-                        reply: None,
-                        tags: None,
-                        facets: Some(vec![atrium_api::app::bsky::richtext::facet::MainData {
-                            features: vec![atrium_api::types::Union::Refs(
-                                atrium_api::app::bsky::richtext::facet::MainFeaturesItem::Link(
-                                    Box::new(
-                                        atrium_api::app::bsky::richtext::facet::LinkData {
-                                            uri: format!(
-                                                "{}/{}",
-                                                ui_endpoint, assoc_event.event.id
-                                            ),
-                                        }
-                                        .into(),
-                                    ),
-                                ),
-                            )],
-                            index: atrium_api::app::bsky::richtext::facet::ByteSliceData {
-                                byte_start: 0,
-                                byte_end: text.len(),
-                            }
-                            .into(),
-                        }
-                        .into()]),
-                        text,
-                    }
-                    .into();
+                let record =
+                    event_posts::record(post_template, ui_endpoint, &assoc_event.event, created_at);
 
                 writes.push(
                     atrium_api::com::atproto::repo::apply_writes::InputWritesItem::Create(
@@ -506,6 +745,46 @@ async fn sync_labels(
         }
     }
 
+    // One definition per series, named after its latest edition.
+    let series_defs = if like_label_kinds.contains(&like_labels::Kind::Series) {
+        let mut latest = std::collections::BTreeMap::new();
+        for (_, assoc_event) in sorted_events.iter() {
+            if let Some(series_id) = &assoc_event.event.series_id {
+                latest.insert(
+                    like_labels::series_label(series_id),
+                    (series_id, &assoc_event.event),
+                );
+            }
+        }
+        latest
+            .into_iter()
+            .map(|(val, (series_id, event))| {
+                let def = label_policies
+                    .for_series(series_id, event)
+                    .definition(val, label_strings::series_locales(event));
+                let mut def: atrium_api::com::atproto::label::defs::LabelValueDefinition =
+                    def.into();
+
+                let ipld_core::ipld::Ipld::Map(extra_data) = &mut def.extra_data else {
+                    unreachable!()
+                };
+                extra_data.insert(
+                    EXTRA_DATA_POST_RKEY.to_string(),
+                    ipld_core::ipld::Ipld::Null,
+                );
+                extra_data.insert(EXTRA_DATA_EVENT_ID.to_string(), ipld_core::ipld::Ipld::Null);
+                extra_data.insert(
+                    EXTRA_DATA_SERIES_ID.to_string(),
+                    ipld_core::serde::to_ipld(series_id).unwrap(),
+                );
+
+                def
+            })
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
     // Update the record.
     {
         let record: atrium_api::app::bsky::labeler::service::Record =
@@ -515,37 +794,17 @@ async fn sync_labels(
                 policies: atrium_api::app::bsky::labeler::defs::LabelerPoliciesData {
                     label_values: sorted_events.iter().filter(|(_, event)| {
                         event.rkey.as_ref().is_some()
-                    }).map(|(_, event)| event.label_id.clone()).collect(),
+                    }).map(|(_, event)| event.label_id.clone())
+                        .chain(series_defs.iter().map(|def| def.identifier.clone()))
+                        .collect(),
                     label_value_definitions: Some(
                         sorted_events.iter()
                             .map(|(_, assoc_event)| {
-                                let mut location = assoc_event.event.venue.clone();
-                                if let Some(address) = &assoc_event.event.address {
-                                    location.push_str(", ");
-                                    location.push_str(address);
-                                }
-                                let mut def: atrium_api::com::atproto::label::defs::LabelValueDefinition = atrium_api::com::atproto::label::defs::LabelValueDefinitionData {
-                                    adult_only: Some(false),
-                                    blurs: "none".to_string(),
-                                    default_setting: Some("warn".to_string()),
-                                    identifier: assoc_event.label_id.clone(),
-                                    locales: vec![atrium_api::com::atproto::label::defs::LabelValueDefinitionStringsData {
-                                        lang: atrium_api::types::string::Language::new(
-                                            assoc_event.event.locale.clone()
-                                        )
-                                        .unwrap(),
-                                        name: assoc_event.event.name.clone(),
-                                        description: format!(
-                                            "📅 {start_date} – {end_date}\n📍 {location}",
-                                            location = location,
-                                            start_date = assoc_event.event.start_date,
-                                            end_date = assoc_event.event.end_date
-                                        ),
-                                    }
-                                    .into()],
-                                    severity: "inform".to_string(),
-                                }
-                                .into();
+                                let def = label_policies.for_event(&assoc_event.event).definition(
+                                    assoc_event.label_id.clone(),
+                                    label_strings::locales(&assoc_event.event),
+                                );
+                                let mut def: atrium_api::com::atproto::label::defs::LabelValueDefinition = def.into();
 
                                 let ipld_core::ipld::Ipld::Map(extra_data) = &mut def.extra_data
                                 else {
@@ -567,6 +826,7 @@ async fn sync_labels(
 
                                 def
                             })
+                            .chain(series_defs)
                             .collect(),
                     ),
                 }
@@ -589,50 +849,209 @@ async fn sync_labels(
         );
     }
 
+    post_events.extend(sorted_events.iter().flat_map(|(_, assoc_event)| {
+        assoc_event
+            .rkey
+            .as_ref()
+            .map(|rkey| (rkey.as_str().to_string(), assoc_event.event.id.clone()))
+    }));
+
+    Ok(Planned {
+        events,
+        commit_cid,
+        writes,
+        renamed,
+        removed_label_ids,
+        posts,
+        post_events,
+        old_definitions,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn sync_labels(
+    reqwest_client: &reqwest::Client,
+    events_url: &str,
+    ui_endpoint: &str,
+    did: &atrium_api::types::string::Did,
+    agent: &atrium_api::agent::Agent<
+        atrium_api::agent::atp_agent::CredentialSession<
+            atrium_api::agent::atp_agent::store::MemorySessionStore,
+            atrium_xrpc_client::reqwest::ReqwestClient,
+        >,
+    >,
+    db_pool: &sqlx::PgPool,
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    events_state: std::sync::Arc<tokio::sync::Mutex<EventsState>>,
+    watchlist: con_posts::Watchlist,
+    announcer: Option<&keydates_announce::Announcer>,
+    like_label_kinds: &[like_labels::Kind],
+    label_policies_path: Option<&std::path::Path>,
+    post_template: event_posts::Template,
+    post_updates: event_posts::Updates,
+) -> Result<(), anyhow::Error> {
+    // Lock the entire events state while labels are syncing.
+    //
+    // This means that we hold the mutex while events are being created, such that any likes on those posts must wait until the mutex is unlocked.
+    // This ensures that we don't get into a state where if someone likes a post but we haven't saved it into the events state yet we end up missing their like.
+    let mut events_state = events_state.lock().await;
+
+    // Finish what the last sync started before reading the repo, and take
+    // over the label work it didn't get to.
+    let mut carried = sync_journal::Plan::default();
+    {
+        let mut db_conn = db_pool.acquire().await?;
+        if let Some(mut journal) = sync_journal::unfinished(&mut db_conn).await? {
+            if !journal.abandoned && journal.applied < journal.plan.chunks.len() {
+                log::info!(
+                    "rolling sync journal {} forward from chunk {}/{}",
+                    journal.id,
+                    journal.applied,
+                    journal.plan.chunks.len()
+                );
+                apply_journal(did, agent, &mut db_conn, &mut journal).await?;
+            }
+            carried = journal.plan;
+        }
+    }
+    let Planned {
+        events,
+        commit_cid,
+        writes,
+        mut renamed,
+        mut removed_label_ids,
+        ..
+    } = plan_sync(
+        reqwest_client,
+        events_url,
+        ui_endpoint,
+        did,
+        agent,
+        carried,
+        like_label_kinds,
+        label_policies_path,
+        post_template,
+        post_updates,
+    )
+    .await?;
+
+    for (old_label_id, id) in relabeled_posts(&events_state, &events) {
+        removed_label_ids.retain(|label_id| *label_id != old_label_id);
+        renamed.entry(old_label_id).or_insert(id);
+    }
+
     log::info!("applying {} write(s)", writes.len());
     log::debug!("applying writes:\n{writes:#?}");
 
-    const CHUNK_SIZE: usize = 200;
-    for chunk in writes.chunks(CHUNK_SIZE) {
-        agent
-            .api
-            .com
-            .atproto
-            .repo
-            .apply_writes(
-                atrium_api::com::atproto::repo::apply_writes::InputData {
-                    repo: did.clone().into(),
-                    swap_commit: None,
-                    validate: Some(true),
-                    writes: chunk.to_vec(),
-                }
-                .into(),
+    let journal = {
+        let mut db_conn = db_pool.acquire().await?;
+        let mut journal = sync_journal::begin(
+            &mut db_conn,
+            sync_journal::Plan {
+                chunks: sync_journal::chunks(writes),
+                renamed: renamed.clone(),
+                removed_label_ids: removed_label_ids.clone(),
+            },
+            commit_cid,
+        )
+        .await?;
+        if !apply_journal(did, agent, &mut db_conn, &mut journal).await? {
+            anyhow::bail!(
+                "repo changed during the sync; the next sync starts over from what is there"
+            );
+        }
+        journal
+    };
+
+    events_state.rkeys_to_ids = events
+        .iter()
+        .flat_map(|(key, event)| event.rkey.as_ref().map(|rkey| (rkey.clone(), key.clone())))
+        .collect();
+
+    // Still under the events lock, so no like can re-apply an old label.
+    for (old_label_id, id) in &renamed {
+        let assoc_event = &events[id];
+        if *old_label_id == assoc_event.label_id {
+            continue;
+        }
+        let exp = assoc_event.event.end_time() + EXPIRY_DATE_GRACE_PERIOD;
+        let mut db_conn = db_pool.acquire().await?;
+        let likers =
+            labels::current_with_vals(&mut db_conn, std::slice::from_ref(old_label_id)).await?;
+        let mut tx = db_conn.begin().await?;
+        for current in &likers {
+            labels::negate(keypair, &mut tx, did, current).await?;
+            labels::apply(
+                keypair,
+                &mut tx,
+                did,
+                &current.uri,
+                &assoc_event.label_id,
+                Some(exp),
+                &current.like_rkey,
             )
             .await?;
+        }
+        label_cap::retarget(&mut tx, old_label_id, Some((&assoc_event.label_id, exp))).await?;
+        like_labels::rename(&mut tx, old_label_id, &assoc_event.label_id).await?;
+        tx.commit().await?;
+        log::info!(
+            "relabeled {} liker(s) from {old_label_id} to {}",
+            likers.len(),
+            assoc_event.label_id
+        );
     }
 
-    events_state.rkeys_to_ids = sorted_events
-        .iter()
-        .flat_map(|(key, event)| {
-            event
-                .rkey
-                .as_ref()
-                .map(|rkey| (rkey.clone(), (*key).clone()))
-        })
-        .collect();
+    // Events whose dates (or time zone) moved since their labels were issued.
+    {
+        let mut db_conn = db_pool.acquire().await?;
+        let mut tx = db_conn.begin().await?;
+        for assoc_event in events.values() {
+            let exp = assoc_event.event.end_time() + EXPIRY_DATE_GRACE_PERIOD;
+            let n =
+                labels::follow_event_exp(keypair, &mut tx, did, &assoc_event.label_id, exp).await?;
+            label_cap::retarget(
+                &mut tx,
+                &assoc_event.label_id,
+                Some((&assoc_event.label_id, exp)),
+            )
+            .await?;
+            if n > 0 {
+                log::info!(
+                    "{} now expires {exp}: re-issued {n} label(s)",
+                    assoc_event.label_id
+                );
+            }
+        }
+        tx.commit().await?;
+    }
+
+    if !removed_label_ids.is_empty() {
+        let mut db_conn = db_pool.acquire().await?;
+        let doomed = labels::current_with_vals(&mut db_conn, &removed_label_ids).await?;
+        let mut tx = db_conn.begin().await?;
+        for current in &doomed {
+            labels::negate(keypair, &mut tx, did, current).await?;
+        }
+        for label_id in &removed_label_ids {
+            label_cap::retarget(&mut tx, label_id, None).await?;
+        }
+        tx.commit().await?;
+        log::info!(
+            "negated {} label(s) for removed event(s): {removed_label_ids:?}",
+            doomed.len()
+        );
+    }
+
+    {
+        let mut db_conn = db_pool.acquire().await?;
+        sync_journal::finish(&mut db_conn, &journal).await?;
+    }
 
     // Rebuild the con-post watchlist (did -> series id) for key-date detection.
     {
         let mut watchlist = watchlist.write().await;
-        watchlist.clear();
-        for assoc_event in events.values() {
-            if let (Some(series_id), Some(bluesky)) = (
-                assoc_event.event.series_id.as_ref(),
-                assoc_event.event.bluesky.as_ref(),
-            ) {
-                watchlist.insert(bluesky.did.clone(), series_id.clone());
-            }
-        }
+        *watchlist = watchlist_of(&events);
         log::info!(
             "watching {} con accounts for key-date posts",
             watchlist.len()
@@ -658,6 +1077,98 @@ async fn sync_labels(
     Ok(())
 }
 
+/// What `sync_labels` would do now, without writing anything. `watchlist`
+/// is the running labeler's, if any, to compare the new one with.
+#[allow(clippy::too_many_arguments)]
+async fn plan_labels(
+    reqwest_client: &reqwest::Client,
+    events_url: &str,
+    ui_endpoint: &str,
+    did: &atrium_api::types::string::Did,
+    agent: &atrium_api::agent::Agent<
+        atrium_api::agent::atp_agent::CredentialSession<
+            atrium_api::agent::atp_agent::store::MemorySessionStore,
+            atrium_xrpc_client::reqwest::ReqwestClient,
+        >,
+    >,
+    db_pool: &sqlx::PgPool,
+    watchlist: Option<&con_posts::Watchlist>,
+    like_label_kinds: &[like_labels::Kind],
+    label_policies_path: Option<&std::path::Path>,
+    post_template: event_posts::Template,
+    post_updates: event_posts::Updates,
+) -> Result<sync_plan::Plan, anyhow::Error> {
+    let mut db_conn = db_pool.acquire().await?;
+
+    let mut unfinished_journal = None;
+    let mut carried = sync_journal::Plan::default();
+    if let Some(journal) = sync_journal::unfinished(&mut db_conn).await? {
+        unfinished_journal = Some(sync_plan::JournalStatus {
+            id: journal.id,
+            applied: journal.applied,
+            chunks: journal.plan.chunks.len(),
+            abandoned: journal.abandoned,
+        });
+        carried = journal.plan;
+    }
+
+    let planned = plan_sync(
+        reqwest_client,
+        events_url,
+        ui_endpoint,
+        did,
+        agent,
+        carried,
+        like_label_kinds,
+        label_policies_path,
+        post_template,
+        post_updates,
+    )
+    .await?;
+
+    let mut plan = sync_plan::Plan::new(
+        &planned.writes,
+        &planned.posts,
+        &planned.post_events,
+        &planned.old_definitions,
+    );
+    plan.unfinished_journal = unfinished_journal;
+
+    let mut renamed = planned.renamed.iter().collect::<Vec<_>>();
+    renamed.sort();
+    for (old_label_id, id) in renamed {
+        let label_id = &planned.events[id].label_id;
+        if old_label_id == label_id {
+            continue;
+        }
+        plan.relabeled.push(sync_plan::Relabel {
+            from: old_label_id.clone(),
+            to: label_id.clone(),
+            labels: labels::current_with_vals(&mut db_conn, std::slice::from_ref(old_label_id))
+                .await?
+                .len(),
+        });
+    }
+    for label_id in &planned.removed_label_ids {
+        plan.negated.push(sync_plan::Negation {
+            label_id: label_id.clone(),
+            labels: labels::current_with_vals(&mut db_conn, std::slice::from_ref(label_id))
+                .await?
+                .len(),
+        });
+    }
+
+    let new_watchlist = watchlist_of(&planned.events);
+    plan.watchlist = match watchlist {
+        Some(watchlist) => {
+            sync_plan::watchlist_changes(Some(&*watchlist.read().await), &new_watchlist)
+        }
+        None => sync_plan::watchlist_changes(None, &new_watchlist),
+    };
+
+    Ok(plan)
+}
+
 async fn read_jetstream_cursor(db_pool: &sqlx::PgPool) -> Result<Option<i64>, anyhow::Error> {
     let mut db_conn = db_pool.acquire().await?;
     Ok(
@@ -667,10 +1178,14 @@ async fn read_jetstream_cursor(db_pool: &sqlx::PgPool) -> Result<Option<i64>, an
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn service_jetstream(
     db_pool: &sqlx::PgPool,
     did: &atrium_api::types::string::Did,
     keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    appview: &appview::AppView,
+    max_labels_per_user: Option<usize>,
+    like_label_kinds: &[like_labels::Kind],
     events_state: std::sync::Arc<tokio::sync::Mutex<EventsState>>,
     jetstream_endpoints: Vec<url::Url>,
     commit_firehose_cursor_every: std::time::Duration,
@@ -693,6 +1208,9 @@ async fn service_jetstream(
             db_pool,
             did,
             keypair,
+            appview,
+            max_labels_per_user,
+            like_label_kinds,
             events_state.clone(),
             &endpoint,
             commit_firehose_cursor_every,
@@ -739,10 +1257,9 @@ async fn service_jetstream(
                 next
             }
         };
-        // Rewind on a host switch only (see Next::rewind); a replay re-emits
-        // labels as duplicate rows (a few seconds on a legacy host, up to an
-        // hour on a v2 host — see DEFAULT_ENDPOINTS), harmless to set-based
-        // label consumers.
+        // Rewind on a host switch only (see Next::rewind); the replay (a few
+        // seconds on a legacy host, up to an hour on a v2 host — see
+        // DEFAULT_ENDPOINTS) re-runs likes that labels::emit then skips.
         cursor = next.rewind(cursor);
         if next.switched {
             log::error!(
@@ -767,6 +1284,9 @@ async fn service_jetstream_once(
     db_pool: &sqlx::PgPool,
     did: &atrium_api::types::string::Did,
     keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    appview: &appview::AppView,
+    max_labels_per_user: Option<usize>,
+    like_label_kinds: &[like_labels::Kind],
     events_state: std::sync::Arc<tokio::sync::Mutex<EventsState>>,
     jetstream_endpoint: &url::Url,
     commit_firehose_cursor_every: std::time::Duration,
@@ -778,7 +1298,11 @@ async fn service_jetstream_once(
     let (js, socket_lifetime) = jetstream::connect(
         jetstream_endpoint,
         jetstream::ConnectOptions {
-            wanted_collections: vec![atrium_api::app::bsky::feed::Like::nsid()],
+            wanted_collections: vec![
+                atrium_api::app::bsky::feed::Like::nsid(),
+                // Blocks of the labeler are opt-outs; see src/opt_out.rs.
+                atrium_api::app::bsky::graph::Block::nsid(),
+            ],
             cursor,
             compress: true,
             ..Default::default()
@@ -803,8 +1327,31 @@ async fn service_jetstream_once(
     {
         let event = event?;
 
-        let jetstream::event::EventKind::Commit { commit } = event.kind else {
-            continue;
+        let commit = match event.kind {
+            jetstream::event::EventKind::Commit { commit } => commit,
+            jetstream::event::EventKind::Identity { identity } => {
+                let mut db_conn = db_pool.acquire().await?;
+                if let Err(e) = handles::record(
+                    &mut db_conn,
+                    identity.did.as_str(),
+                    identity.handle.as_deref(),
+                    identity.time,
+                )
+                .await
+                {
+                    log::error!("identity event for {}: {e}", identity.did.as_str());
+                }
+                continue;
+            }
+            jetstream::event::EventKind::Account { account } => {
+                // Not fatal to the connection: the next event for the
+                // account (or a reactivation) tries again.
+                if let Err(e) = service_account(db_pool, did, keypair, appview, &account).await {
+                    log::error!("account event for {}: {e}", account.did.as_str());
+                }
+                continue;
+            }
+            _ => continue,
         };
 
         let mut db_conn = db_pool.acquire().await?;
@@ -812,6 +1359,27 @@ async fn service_jetstream_once(
         async {
             match commit.operation {
                 jetstream::event::CommitOperation::Create { record, .. } => {
+                    if let atrium_api::record::KnownRecord::AppBskyGraphBlock(block) = &record {
+                        if block.subject != *did {
+                            return Ok(());
+                        }
+                        let mut tx = db_conn.begin().await?;
+                        let n = opt_out::opt_out(
+                            keypair,
+                            &mut tx,
+                            did,
+                            event.did.as_str(),
+                            &commit.rkey,
+                        )
+                        .await?;
+                        tx.commit().await?;
+                        log::info!(
+                            "{} blocked us, opting out: negated {n} label(s)",
+                            handles::describe(&mut db_conn, event.did.as_str()).await
+                        );
+                        return Ok(());
+                    }
+
                     let atrium_api::record::KnownRecord::AppBskyFeedLike(like) = record else {
                         return Ok(());
                     };
@@ -836,6 +1404,15 @@ async fn service_jetstream_once(
                         return Ok(());
                     }
 
+                    if opt_out::is_opted_out(&mut db_conn, event.did.as_str()).await? {
+                        log::info!(
+                            "{} opted out, not labeling like {}",
+                            handles::describe(&mut db_conn, event.did.as_str()).await,
+                            commit.rkey
+                        );
+                        return Ok(());
+                    }
+
                     let events_state = events_state.lock().await;
 
                     let Some(id) = events_state
@@ -851,75 +1428,144 @@ async fn service_jetstream_once(
 
                     let assoc_event = events_state.events.get(&id).unwrap();
 
-                    let label: atrium_api::com::atproto::label::defs::Label =
-                        atrium_api::com::atproto::label::defs::LabelData {
-                            cts: atrium_api::types::string::Datetime::new(
-                                chrono::DateTime::from_timestamp_micros(event.time_us as i64)
-                                    .unwrap()
-                                    .fixed_offset(),
-                            ),
-                            exp: Some(atrium_api::types::string::Datetime::new(
-                                (assoc_event.event.end_time() + EXPIRY_DATE_GRACE_PERIOD)
-                                    .to_utc()
-                                    .fixed_offset(),
-                            )),
-                            src: did.clone(),
-                            cid: None,
-                            neg: None,
-                            uri: event.did.to_string(),
-                            val: assoc_event.label_id.clone(),
-                            sig: None,
-                            ver: Some(1),
+                    let who = handles::describe(&mut db_conn, event.did.as_str()).await;
+                    let mut tx = db_conn.begin().await?;
+                    for (val, exp) in like_labels::for_event(like_label_kinds, assoc_event) {
+                        let label: atrium_api::com::atproto::label::defs::Label =
+                            atrium_api::com::atproto::label::defs::LabelData {
+                                cts: atrium_api::types::string::Datetime::new(
+                                    chrono::DateTime::from_timestamp_micros(event.time_us as i64)
+                                        .unwrap()
+                                        .fixed_offset(),
+                                ),
+                                exp: exp.map(|exp| {
+                                    atrium_api::types::string::Datetime::new(exp.fixed_offset())
+                                }),
+                                src: did.clone(),
+                                cid: None,
+                                neg: None,
+                                uri: event.did.to_string(),
+                                val,
+                                sig: None,
+                                ver: Some(1),
+                            }
+                            .into();
+
+                        // A series label another like already gives stays as is.
+                        if !like_labels::record(&mut tx, &label.uri, &commit.rkey, &label.val)
+                            .await?
+                        {
+                            log::info!("{who} already has {} from another like", label.val);
+                            continue;
                         }
-                        .into();
-
-                    log::info!("applying label: {:?}", label);
 
+                        let applied = match max_labels_per_user {
+                            Some(max) => {
+                                let capped =
+                                    label_cap::emit(keypair, &mut tx, &label, &commit.rkey, max)
+                                        .await?;
+                                if !capped.evicted.is_empty() {
+                                    log::info!(
+                                        "{who} at the cap of {max} label(s), evicted {:?}",
+                                        capped.evicted
+                                    );
+                                }
+                                if capped.deferred {
+                                    log::info!(
+                                        "{who} at the cap of {max} label(s), deferred: {:?}",
+                                        label
+                                    );
+                                    continue;
+                                }
+                                capped.applied
+                            }
+                            None => labels::emit(keypair, &mut tx, &label, &commit.rkey).await?,
+                        };
+                        if applied.is_some() {
+                            log::info!("applied label to {who}: {:?}", label);
+                        } else {
+                            log::info!("label already applied to {who} (replay?): {:?}", label);
+                        }
+                    }
+                    tx.commit().await?;
+                }
+                jetstream::event::CommitOperation::Delete { .. }
+                    if commit.collection.as_str() == atrium_api::app::bsky::graph::Block::NSID =>
+                {
                     let mut tx = db_conn.begin().await?;
-                    labels::emit(keypair, &mut tx, &label, &commit.rkey).await?;
+                    let opted_in =
+                        opt_out::opt_in(&mut tx, event.did.as_str(), &commit.rkey).await?;
                     tx.commit().await?;
+                    if opted_in {
+                        log::info!(
+                            "{} unblocked us, opting back in",
+                            handles::describe(&mut db_conn, event.did.as_str()).await
+                        );
+                    }
                 }
                 jetstream::event::CommitOperation::Delete { .. } => {
                     let uri = event.did.to_string();
 
-                    let Some(val) = sqlx::query_scalar!(
-                        r#"
-                        SELECT val FROM labels
-                        WHERE like_rkey = $1 AND uri = $2 AND NOT neg
-                        ORDER BY seq DESC
-                        LIMIT 1
-                        "#,
-                        commit.rkey,
-                        uri
-                    )
-                    .fetch_optional(&mut *db_conn)
-                    .await?
-                    else {
-                        return Ok(());
+                    // Most deletions on the network are of likes we never
+                    // labeled: find out from the like alone before anything else.
+                    let mut tx = db_conn.begin().await?;
+                    let gave = like_labels::unlike(&mut tx, &uri, &commit.rkey).await?;
+                    let forgot = match max_labels_per_user {
+                        Some(_) => label_cap::forget(&mut tx, &uri, &commit.rkey).await?,
+                        None => false,
                     };
-
-                    let label: atrium_api::com::atproto::label::defs::Label =
-                        atrium_api::com::atproto::label::defs::LabelData {
-                            cts: atrium_api::types::string::Datetime::new(
-                                chrono::DateTime::from_timestamp_micros(event.time_us as i64)
-                                    .unwrap()
-                                    .fixed_offset(),
-                            ),
-                            exp: None,
-                            src: did.clone(),
-                            cid: None,
-                            neg: Some(true),
-                            uri,
-                            val,
-                            sig: None,
-                            ver: Some(1),
+                    if gave.is_empty() {
+                        tx.commit().await?;
+                        if forgot {
+                            log::info!(
+                                "forgot deferred like {} of {}",
+                                commit.rkey,
+                                handles::describe(&mut db_conn, &uri).await
+                            );
                         }
-                        .into();
-
-                    log::info!("removing label: {:?}", label);
+                        return Ok(());
+                    }
 
-                    let mut tx = db_conn.begin().await?;
-                    labels::emit(keypair, &mut tx, &label, &commit.rkey).await?;
+                    let who = handles::describe(&mut tx, &uri).await;
+                    if forgot {
+                        log::info!("forgot deferred like {} of {who}", commit.rkey);
+                    }
+                    let mut negated = false;
+                    for val in gave {
+                        let label: atrium_api::com::atproto::label::defs::Label =
+                            atrium_api::com::atproto::label::defs::LabelData {
+                                cts: atrium_api::types::string::Datetime::new(
+                                    chrono::DateTime::from_timestamp_micros(event.time_us as i64)
+                                        .unwrap()
+                                        .fixed_offset(),
+                                ),
+                                exp: None,
+                                src: did.clone(),
+                                cid: None,
+                                neg: Some(true),
+                                uri: uri.clone(),
+                                val,
+                                sig: None,
+                                ver: Some(1),
+                            }
+                            .into();
+
+                        if labels::emit(keypair, &mut tx, &label, &commit.rkey)
+                            .await?
+                            .is_some()
+                        {
+                            log::info!("removed label from {who}: {:?}", label);
+                            negated = true;
+                        } else {
+                            log::info!("label already removed from {who} (replay?): {:?}", label);
+                        }
+                    }
+                    if let (Some(max), true) = (max_labels_per_user, negated) {
+                        let promoted = label_cap::promote(keypair, &mut tx, did, &uri, max).await?;
+                        if !promoted.is_empty() {
+                            log::info!("applied deferred label(s) to {who}: {promoted:?}");
+                        }
+                    }
                     tx.commit().await?;
                 }
                 _ => {}
@@ -954,6 +1600,166 @@ async fn service_jetstream_once(
     Ok(cursor)
 }
 
+#[derive(serde::Deserialize)]
+struct ReconcileParams {
+    #[serde(default)]
+    fix: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct PlanParams {
+    #[serde(default)]
+    format: sync_plan::Format,
+}
+
+/// What `backfill::Backfill` needs, owned, for the handlers and tasks that
+/// backfill or reconcile.
+#[derive(Clone)]
+struct Reconciler {
+    db_pool: sqlx::PgPool,
+    appview: appview::AppView,
+    resolver: did_doc::Resolver,
+    keypair: std::sync::Arc<atrium_crypto::keypair::Secp256k1Keypair>,
+    did: atrium_api::types::string::Did,
+    max_labels_per_user: Option<usize>,
+    like_label_kinds: Vec<like_labels::Kind>,
+    events_state: std::sync::Arc<tokio::sync::Mutex<EventsState>>,
+    /// Held while one runs: they would only trip over each other.
+    running: std::sync::Arc<tokio::sync::Mutex<()>>,
+}
+
+type Reconciled = Vec<(String, Result<backfill::Outcome, backfill::Error>)>;
+
+impl Reconciler {
+    /// Go over every event post as of the last sync, logging each event's
+    /// discrepancies and the total. A backfill is a reconciliation that fixes
+    /// and skips the per-label rkey check.
+    async fn run(
+        &self,
+        what: &str,
+        fix: bool,
+        check_rkeys: bool,
+    ) -> (backfill::Outcome, Reconciled) {
+        let mut posts = self
+            .events_state
+            .lock()
+            .await
+            .events
+            .values()
+            .filter_map(|assoc_event| {
+                Some(backfill::EventPost {
+                    label_id: assoc_event.label_id.clone(),
+                    uri: format!(
+                        "at://{}/{}/{}",
+                        self.did.as_str(),
+                        atrium_api::app::bsky::feed::Post::NSID,
+                        assoc_event.rkey.as_ref()?.as_str()
+                    ),
+                    labels: like_labels::for_event(&self.like_label_kinds, assoc_event),
+                })
+            })
+            .collect::<Vec<_>>();
+        posts.sort_by(|a, b| a.label_id.cmp(&b.label_id));
+
+        log::info!("{what}: {} event post(s)", posts.len());
+        let outcomes = backfill::Backfill {
+            db_pool: &self.db_pool,
+            appview: &self.appview,
+            resolver: &self.resolver,
+            keypair: &self.keypair,
+            src: &self.did,
+            max_labels_per_user: self.max_labels_per_user,
+            fix,
+            check_rkeys,
+        }
+        .run(&posts)
+        .await;
+
+        let mut total = backfill::Outcome::default();
+        for (label_id, outcome) in &outcomes {
+            match outcome {
+                Ok(outcome) => {
+                    if !outcome.is_clean() {
+                        log::warn!(
+                            "{what}: {label_id}: {outcome}{}",
+                            if fix { " (fixed)" } else { "" }
+                        );
+                    }
+                    total.add(outcome);
+                }
+                Err(e) => log::error!("{what}: {label_id}: {e}"),
+            }
+        }
+        log::info!("{what} done: {total}");
+        (total, outcomes)
+    }
+}
+
+/// Takedowns, suspensions and deactivations set the account's labels aside
+/// and deletions drop them; see src/accounts.rs. On reactivation the labels
+/// whose likes the AppView still has come back.
+async fn service_account(
+    db_pool: &sqlx::PgPool,
+    did: &atrium_api::types::string::Did,
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    appview: &appview::AppView,
+    account: &jetstream::event::Account,
+) -> Result<(), anyhow::Error> {
+    use jetstream::event::AccountStatus;
+
+    let uri = account.did.as_str();
+    let mut db_conn = db_pool.acquire().await?;
+    if !accounts::tracked(&mut db_conn, uri).await? {
+        return Ok(());
+    }
+    let who = handles::describe(&mut db_conn, uri).await;
+
+    if account.active {
+        let suspended = accounts::suspended(&mut db_conn, uri).await?;
+        if suspended.is_empty() {
+            return Ok(());
+        }
+        let mut surviving = vec![];
+        for label in suspended {
+            match appview.like_exists(uri, &label.like_rkey).await {
+                Ok(true) => surviving.push(label),
+                Ok(false) => {}
+                Err(e) => {
+                    // Keep everything set aside rather than drop labels on a
+                    // lookup failure; the next reactivation tries again.
+                    log::error!("{who} reactivated, but could not check its likes: {e}");
+                    return Ok(());
+                }
+            }
+        }
+        let mut tx = db_conn.begin().await?;
+        let n = accounts::restore(keypair, &mut tx, did, uri, &surviving).await?;
+        tx.commit().await?;
+        log::info!("{who} reactivated: restored {n} label(s)");
+        return Ok(());
+    }
+
+    let purge = match account.status {
+        Some(AccountStatus::Deleted) => true,
+        Some(AccountStatus::TakenDown)
+        | Some(AccountStatus::Suspended)
+        | Some(AccountStatus::Deactivated)
+        | None => false,
+        Some(AccountStatus::Throttled) | Some(AccountStatus::Other(_)) => return Ok(()),
+    };
+    let mut tx = db_conn.begin().await?;
+    let n = accounts::suspend(keypair, &mut tx, did, uri, purge).await?;
+    tx.commit().await?;
+    if n > 0 {
+        log::info!(
+            "{who} is {:?}: negated {n} label(s){}",
+            account.status,
+            if purge { "" } else { ", kept for reactivation" }
+        );
+    }
+    Ok(())
+}
+
 /// A pinned `jetstream_endpoint` goes first; the list follows, minus any
 /// duplicate of the pin, so failover still has somewhere to go. Pin plus an
 /// empty list is the exclusive pin: `[pin]`, which never switches.
@@ -974,16 +1780,40 @@ fn jetstream_dial_order(pinned: Option<&url::Url>, list: &[url::Url]) -> Vec<url
 async fn main() -> Result<(), anyhow::Error> {
     env_logger::init();
 
+    // `--plan [--json]`: print what a label sync would do, then exit.
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let plan_format = match &args[..] {
+        [] => None,
+        [flag] if flag == "--plan" => Some(sync_plan::Format::Text),
+        [flag, json] if flag == "--plan" && json == "--json" => Some(sync_plan::Format::Json),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "usage: bsky-event-ingester [--plan [--json]]"
+            ))
+        }
+    };
+
     let config: Config = config::Config::builder()
         .add_source(config::File::with_name("config.toml"))
         .set_default("bsky_endpoint", "https://bsky.social")?
         .set_default("events_url", "https://data.cons.fyi/current.jsonl")?
         .set_default("keypair_path", "signing.key")?
+        .set_default("plc_url", did_doc::DEFAULT_PLC_URL)?
+        .set_default("on_label_key_mismatch", "refuse")?
+        .set_default("appview_url", appview::DEFAULT_URL)?
+        .set_default("deferred_label_promote_every_secs", 15 * 60)?
+        .set_default("like_labels", vec!["edition"])?
+        .set_default("backfill_on_startup", false)?
+        .set_default("reconcile_fix", false)?
         .set_default("ui_endpoint", "https://cons.fyi")?
+        .set_default("post_template", "plain")?
+        .set_default("post_updates", "dry_run")?
         .set_default("jetstream_endpoints", jetstream::DEFAULT_ENDPOINTS.to_vec())?
         .set_default("label_sync_delay_secs", 60 * 60)?
         .set_default("ingester_bind", "127.0.0.1:3002")?
         .set_default("commit_firehose_cursor_every_secs", 5)?
+        .set_default("label_compaction_every_secs", 60 * 60)?
+        .set_default("label_compaction_grace_secs", 7 * 24 * 60 * 60)?
         .set_default("con_post_debounce_secs", 15 * 60)?
         .set_default("con_posts_daily_cap", 30)?
         .set_default("telegram_dry_run", true)?
@@ -991,9 +1821,20 @@ async fn main() -> Result<(), anyhow::Error> {
         .build()?
         .try_deserialize()?;
     log::info!("config: {config:?}");
+    if config.max_labels_per_user == Some(0) {
+        return Err(anyhow::anyhow!(
+            "max_labels_per_user = 0 would label no one; leave it unset for no cap"
+        ));
+    }
+    if config.like_labels.is_empty() {
+        return Err(anyhow::anyhow!(
+            "like_labels is empty, so likes would label no one"
+        ));
+    }
 
-    let keypair =
-        atrium_crypto::keypair::Secp256k1Keypair::import(&std::fs::read(&config.keypair_path)?)?;
+    let keypair = std::sync::Arc::new(atrium_crypto::keypair::Secp256k1Keypair::import(
+        &std::fs::read(&config.keypair_path)?,
+    )?);
 
     let events_state = std::sync::Arc::new(tokio::sync::Mutex::new(EventsState {
         rkeys_to_ids: std::collections::HashMap::new(),
@@ -1001,6 +1842,10 @@ async fn main() -> Result<(), anyhow::Error> {
     }));
 
     let reqwest_client = reqwest::Client::new();
+    let appview = appview::AppView {
+        reqwest_client: reqwest_client.clone(),
+        url: config.appview_url.clone(),
+    };
 
     let session = atrium_api::agent::atp_agent::CredentialSession::new(
         atrium_xrpc_client::reqwest::ReqwestClientBuilder::new(&config.bsky_endpoint)
@@ -1015,8 +1860,68 @@ async fn main() -> Result<(), anyhow::Error> {
 
     let did = agent.did().await.unwrap();
 
+    let resolver = did_doc::Resolver {
+        reqwest_client: reqwest_client.clone(),
+        plc_url: config.plc_url.clone(),
+    };
+
+    match resolver.verify_label_key(&did, &keypair).await {
+        Ok(()) => log::info!("signing key matches the label key published for {did:?}"),
+        // The directory being down says nothing about the key; don't let it
+        // keep the labeler down too.
+        Err(e @ (did_doc::Error::Reqwest(_) | did_doc::Error::Url(_))) => {
+            log::warn!("could not check the signing key against {did:?}'s DID document: {e}")
+        }
+        // A document that publishes no label key, the wrong one, or is for
+        // someone else: either way clients have nothing our signatures
+        // verify against.
+        Err(e) => match config.on_label_key_mismatch {
+            OnLabelKeyMismatch::Refuse => {
+                return Err(anyhow::anyhow!(
+                    "refusing to start: {e} (set on_label_key_mismatch = \"degraded\" to label anyway)"
+                ));
+            }
+            OnLabelKeyMismatch::Degraded => {
+                log::error!(
+                    "DEGRADED: {e}; clients will reject every label signed until this is fixed"
+                )
+            }
+        },
+    }
+
     let db_pool = sqlx::PgPool::connect(&config.postgres_url).await?;
 
+    if let Some(format) = plan_format {
+        let plan = plan_labels(
+            &reqwest_client,
+            &config.events_url,
+            &config.ui_endpoint,
+            &did,
+            &agent,
+            &db_pool,
+            None,
+            &config.like_labels,
+            config.label_policies_path.as_deref(),
+            config.post_template,
+            config.post_updates,
+        )
+        .await?;
+        match format {
+            sync_plan::Format::Text => print!("{plan}"),
+            sync_plan::Format::Json => println!("{}", serde_json::to_string_pretty(&plan)?),
+        }
+        return Ok(());
+    }
+
+    {
+        let mut tx = db_pool.begin().await?;
+        let n = labels::rebuild_current_if_empty(&mut tx).await?;
+        tx.commit().await?;
+        if n > 0 {
+            log::info!("rebuilt {n} current_labels row(s) from the label log");
+        }
+    }
+
     let watchlist: con_posts::Watchlist =
         std::sync::Arc::new(tokio::sync::RwLock::new(std::collections::HashMap::new()));
 
@@ -1038,49 +1943,188 @@ async fn main() -> Result<(), anyhow::Error> {
         &config.ui_endpoint,
         &did,
         &agent,
+        &db_pool,
+        &keypair,
         events_state.clone(),
         watchlist.clone(),
         Some(&announcer),
+        &config.like_labels,
+        config.label_policies_path.as_deref(),
+        config.post_template,
+        config.post_updates,
     )
     .await?;
 
     let listener = tokio::net::TcpListener::bind(&config.ingester_bind).await?;
 
-    let app = axum::Router::new().route(
-        "/trigger",
-        axum::routing::post({
-            let did = did.clone();
-            let events_state = events_state.clone();
-            let watchlist = watchlist.clone();
-            let announcer = announcer.clone();
-            let triggering = std::sync::Arc::new(tokio::sync::Mutex::new(()));
-            || async move {
-                let Ok(_guard) = triggering.try_lock() else {
-                    return (axum::http::StatusCode::CONFLICT, "already in progress!")
-                        .into_response();
-                };
+    let reconciler = Reconciler {
+        db_pool: db_pool.clone(),
+        appview: appview.clone(),
+        resolver: resolver.clone(),
+        keypair: keypair.clone(),
+        did: did.clone(),
+        max_labels_per_user: config.max_labels_per_user,
+        like_label_kinds: config.like_labels.clone(),
+        events_state: events_state.clone(),
+        running: std::sync::Arc::new(tokio::sync::Mutex::new(())),
+    };
 
-                match sync_labels(
-                    &reqwest_client,
-                    &config.events_url,
-                    &config.ui_endpoint,
-                    &did,
-                    &agent,
-                    events_state,
-                    watchlist,
-                    Some(&announcer),
-                )
-                .await
-                {
-                    Ok(_) => (axum::http::StatusCode::OK, "ok :)").into_response(),
-                    Err(e) => {
-                        log::error!("Failed to sync labels: {e}");
-                        (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "uh oh :(").into_response()
+    let (new_labels_tx, new_labels_rx) = tokio::sync::watch::channel(());
+
+    let triggering = std::sync::Arc::new(tokio::sync::Mutex::new(()));
+    let app = axum::Router::new()
+        .route(
+            "/plan",
+            axum::routing::get({
+                let reqwest_client = reqwest_client.clone();
+                let events_url = config.events_url.clone();
+                let ui_endpoint = config.ui_endpoint.clone();
+                let did = did.clone();
+                let agent = agent.clone();
+                let db_pool = db_pool.clone();
+                let watchlist = watchlist.clone();
+                let like_labels = config.like_labels.clone();
+                let label_policies_path = config.label_policies_path.clone();
+                let post_template = config.post_template;
+                let post_updates = config.post_updates;
+                let triggering = triggering.clone();
+                move |axum::extract::Query(params): axum::extract::Query<PlanParams>| async move {
+                    // Not while a sync is writing: it would be planned half-done.
+                    let Ok(_guard) = triggering.try_lock() else {
+                        return (axum::http::StatusCode::CONFLICT, "sync in progress!")
+                            .into_response();
+                    };
+
+                    match plan_labels(
+                        &reqwest_client,
+                        &events_url,
+                        &ui_endpoint,
+                        &did,
+                        &agent,
+                        &db_pool,
+                        Some(&watchlist),
+                        &like_labels,
+                        label_policies_path.as_deref(),
+                        post_template,
+                        post_updates,
+                    )
+                    .await
+                    {
+                        Ok(plan) => match params.format {
+                            sync_plan::Format::Text => plan.to_string().into_response(),
+                            sync_plan::Format::Json => axum::Json(plan).into_response(),
+                        },
+                        Err(e) => {
+                            log::error!("Failed to plan label sync: {e}");
+                            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "uh oh :(")
+                                .into_response()
+                        }
                     }
                 }
-            }
-        }),
-    );
+            }),
+        )
+        .route(
+            "/trigger",
+            axum::routing::post({
+                let did = did.clone();
+                let db_pool = db_pool.clone();
+                let keypair = keypair.clone();
+                let events_state = events_state.clone();
+                let watchlist = watchlist.clone();
+                let announcer = announcer.clone();
+                let like_labels = config.like_labels.clone();
+                let label_policies_path = config.label_policies_path.clone();
+                let post_template = config.post_template;
+                let post_updates = config.post_updates;
+                let triggering = triggering.clone();
+                move || async move {
+                    let Ok(_guard) = triggering.try_lock() else {
+                        return (axum::http::StatusCode::CONFLICT, "already in progress!")
+                            .into_response();
+                    };
+
+                    match sync_labels(
+                        &reqwest_client,
+                        &config.events_url,
+                        &config.ui_endpoint,
+                        &did,
+                        &agent,
+                        &db_pool,
+                        &keypair,
+                        events_state,
+                        watchlist,
+                        Some(&announcer),
+                        &like_labels,
+                        label_policies_path.as_deref(),
+                        post_template,
+                        post_updates,
+                    )
+                    .await
+                    {
+                        Ok(_) => (axum::http::StatusCode::OK, "ok :)").into_response(),
+                        Err(e) => {
+                            log::error!("Failed to sync labels: {e}");
+                            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "uh oh :(")
+                                .into_response()
+                        }
+                    }
+                }
+            }),
+        )
+        .route(
+            "/backfill",
+            axum::routing::post({
+                let reconciler = reconciler.clone();
+                move || async move {
+                    let Ok(guard) = reconciler.running.clone().try_lock_owned() else {
+                        return (axum::http::StatusCode::CONFLICT, "already in progress!")
+                            .into_response();
+                    };
+
+                    // Detached: a backfill outlives any reasonable request.
+                    tokio::spawn(async move {
+                        let _guard = guard;
+                        reconciler.run("backfill", true, false).await;
+                    });
+                    (axum::http::StatusCode::ACCEPTED, "started, see the logs").into_response()
+                }
+            }),
+        )
+        .route(
+            "/reconcile",
+            axum::routing::post({
+                let reconciler = reconciler.clone();
+                move |axum::extract::Query(params): axum::extract::Query<ReconcileParams>| async move {
+                    let Ok(_guard) = reconciler.running.try_lock() else {
+                        return (axum::http::StatusCode::CONFLICT, "already in progress!")
+                            .into_response();
+                    };
+
+                    let (total, outcomes) = reconciler.run("reconcile", params.fix, true).await;
+                    axum::Json(serde_json::json!({
+                        "total": total,
+                        "events": outcomes
+                            .into_iter()
+                            .map(|(label_id, outcome)| {
+                                (
+                                    label_id,
+                                    match outcome {
+                                        Ok(outcome) => serde_json::json!(outcome),
+                                        Err(e) => serde_json::json!({ "error": e.to_string() }),
+                                    },
+                                )
+                            })
+                            .collect::<serde_json::Map<_, _>>(),
+                    }))
+                    .into_response()
+                }
+            }),
+        )
+        .merge(xrpc::router(xrpc::State {
+            db_pool: db_pool.clone(),
+            did: did.clone(),
+            new_labels: new_labels_rx,
+        }));
 
     let jetstream_endpoints = jetstream_dial_order(
         config.jetstream_endpoint.as_ref(),
@@ -1109,6 +2153,9 @@ async fn main() -> Result<(), anyhow::Error> {
                 &db_pool,
                 &did,
                 &keypair,
+                &appview,
+                config.max_labels_per_user,
+                &config.like_labels,
                 events_state.clone(),
                 jetstream_endpoints,
                 std::time::Duration::from_secs(config.commit_firehose_cursor_every_secs),
@@ -1116,6 +2163,72 @@ async fn main() -> Result<(), anyhow::Error> {
             .await?;
             Ok::<_, anyhow::Error>(())
         },
+        async {
+            // Wake subscribeLabels sockets on new labels.
+            xrpc::subscribe_labels::watch(&db_pool, new_labels_tx).await?;
+            unreachable!();
+
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        },
+        async {
+            // Prune the label log.
+            labels::periodic_compact(
+                &db_pool,
+                std::time::Duration::from_secs(config.label_compaction_every_secs),
+                chrono::Duration::seconds(config.label_compaction_grace_secs as i64),
+            )
+            .await;
+            unreachable!();
+
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        },
+        async {
+            // Catch up on likes missed while down, next to the firehose.
+            if config.backfill_on_startup {
+                let _guard = reconciler.running.lock().await;
+                reconciler.run("backfill", true, false).await;
+            }
+            Ok::<_, anyhow::Error>(())
+        },
+        async {
+            // Check that labels and likes agree.
+            let Some(every) = config.reconcile_every_secs else {
+                return Ok(());
+            };
+            let every = std::time::Duration::from_secs(every);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let _guard = reconciler.running.lock().await;
+                reconciler
+                    .run("reconcile", config.reconcile_fix, true)
+                    .await;
+            }
+
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        },
+        async {
+            // Apply deferred likes as capped accounts' labels expire.
+            let Some(max) = config.max_labels_per_user else {
+                return Ok(());
+            };
+            label_cap::periodic_promote(
+                &db_pool,
+                &keypair,
+                &did,
+                max,
+                std::time::Duration::from_secs(config.deferred_label_promote_every_secs),
+            )
+            .await;
+            unreachable!();
+
+            #[allow(unreachable_code)]
+            Ok::<_, anyhow::Error>(())
+        },
         async {
             // Serve labeler.
             axum::serve(listener, app).await?;
@@ -1165,6 +2278,101 @@ mod tests {
         }
     }
 
+    fn assoc_event(id: &str, previous_ids: &[&str]) -> AssociatedEvent {
+        let event: events::IngestedEvent = serde_json::from_value(serde_json::json!({
+            "id": id,
+            "name": id,
+            "venue": "somewhere",
+            "locale": "en",
+            "startDate": "2025-07-03",
+            "endDate": "2025-07-06",
+            "previousIds": previous_ids,
+        }))
+        .unwrap();
+        AssociatedEvent {
+            rkey: None,
+            label_id: events::id_to_label(&event.id),
+            event,
+        }
+    }
+
+    fn old_event(id: &str) -> OldEvent {
+        OldEvent {
+            rkey: Some(atrium_api::types::string::RecordKey::new("3kabc".to_string()).unwrap()),
+            id: id.to_string(),
+        }
+    }
+
+    #[test]
+    fn renames_follow_previous_ids() {
+        let events = [
+            assoc_event("anthrocon-2025-pittsburgh", &["anthrocon-2025"]),
+            assoc_event("mff-2025", &[]),
+            // listed as previous, but still published under its own id
+            assoc_event("fwa-2025", &["mff-2025"]),
+        ]
+        .into_iter()
+        .map(|assoc_event| (assoc_event.event.id.clone(), assoc_event))
+        .collect();
+        let old_events = [
+            ("anthrocon-mmxxv", "anthrocon-2025"),
+            ("mff-mmxxv", "mff-2025"),
+            ("gone-mmxxv", "gone-2025"),
+        ]
+        .into_iter()
+        .map(|(label_id, id)| (label_id.to_string(), old_event(id)))
+        .collect();
+
+        assert_eq!(
+            renamed_events(&old_events, &events),
+            [(
+                "anthrocon-mmxxv".to_string(),
+                "anthrocon-2025-pittsburgh".to_string()
+            )]
+            .into_iter()
+            .collect()
+        );
+    }
+
+    #[test]
+    fn relabeled_posts_follow_the_post() {
+        let rkey = |s: &str| atrium_api::types::string::RecordKey::new(s.to_string()).unwrap();
+        let with = |id: &str, rkey_: &str, label_id: &str| {
+            let mut assoc_event = assoc_event(id, &[]);
+            assoc_event.rkey = Some(rkey(rkey_));
+            assoc_event.label_id = label_id.to_string();
+            (id.to_string(), assoc_event)
+        };
+        let old = EventsState {
+            rkeys_to_ids: [
+                (rkey("3kac"), "anthrocon-2025".to_string()),
+                (rkey("3kmff"), "mff-2025".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            events: [
+                with("anthrocon-2025", "3kac", "ac-mmxxv"),
+                with("mff-2025", "3kmff", "mff-mmxxv"),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let events = [
+            with("anthrocon-2025", "3kac", "anthrocon-mmxxv"),
+            with("mff-2025", "3kmff", "mff-mmxxv"),
+            with("fwa-2026", "3kfwa", "fwa-mmxxvi"),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            relabeled_posts(&old, &events),
+            [("ac-mmxxv".to_string(), "anthrocon-2025".to_string())]
+                .into_iter()
+                .collect()
+        );
+    }
+
     // Legacy hosts honour a timestamp cursor exactly; the v2 hosts clamp a
     // fresh cursor to the start of their active log segment (up to an hour
     // of replay per connect), so they must never be dialed first.
@@ -0,0 +1,135 @@
+//! Per-user opt-out: blocking the labeler account takes every label we have
+//! on the blocker off (and drops likes deferred by `label_cap`), and no like
+//! of theirs is labeled until the block is removed. Likes made while opted
+//! out stay unlabeled after opting back in; liking again applies the label.
+
+/// Record `did`'s block of us and negate all of its labels. A replayed block
+/// is a no-op apart from re-checking that nothing is left on. Returns the
+/// number of labels negated.
+pub async fn opt_out(
+    keypair: &atrium_crypto::keypair::Secp256k1Keypair,
+    tx: &mut sqlx::PgTransaction<'_>,
+    src: &atrium_api::types::string::Did,
+    did: &str,
+    block_rkey: &str,
+) -> Result<u64, crate::labels::EmitError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO opt_outs (did, block_rkey) VALUES ($1, $2)
+        ON CONFLICT (did) DO UPDATE SET block_rkey = excluded.block_rkey
+        "#,
+        did,
+        block_rkey,
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(r#"DELETE FROM deferred_labels WHERE uri = $1"#, did)
+        .execute(&mut **tx)
+        .await?;
+
+    let mut n = 0;
+    for current in crate::labels::current(tx, did).await? {
+        if crate::labels::negate(keypair, tx, src, &current)
+            .await?
+            .is_some()
+        {
+            n += 1;
+        }
+    }
+    Ok(n)
+}
+
+/// Forget `did`'s opt-out if `block_rkey` is the block that made it. Returns
+/// whether it was.
+pub async fn opt_in(
+    tx: &mut sqlx::PgTransaction<'_>,
+    did: &str,
+    block_rkey: &str,
+) -> Result<bool, sqlx::Error> {
+    Ok(sqlx::query!(
+        r#"
+        DELETE FROM opt_outs WHERE did = $1 AND block_rkey = $2
+        "#,
+        did,
+        block_rkey,
+    )
+    .execute(&mut **tx)
+    .await?
+    .rows_affected()
+        > 0)
+}
+
+pub async fn is_opted_out(
+    db_conn: &mut sqlx::PgConnection,
+    did: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM opt_outs WHERE did = $1) AS "opted_out!"
+        "#,
+        did
+    )
+    .fetch_one(db_conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn block_negates_and_unblock_forgets() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let src = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+        let exp = Some(chrono::Utc::now() + chrono::Days::new(30));
+
+        let mut tx = db_pool.begin().await.unwrap();
+        for (uri, val, like_rkey) in [
+            ("did:plc:a", "alpha", "3ka"),
+            ("did:plc:a", "beta", "3kb"),
+            ("did:plc:b", "alpha", "3kc"),
+        ] {
+            crate::labels::apply(&keypair, &mut tx, &src, uri, val, exp, like_rkey)
+                .await
+                .unwrap();
+        }
+        assert_eq!(
+            opt_out(&keypair, &mut tx, &src, "did:plc:a", "3kblock")
+                .await
+                .unwrap(),
+            2
+        );
+        // replayed
+        assert_eq!(
+            opt_out(&keypair, &mut tx, &src, "did:plc:a", "3kblock")
+                .await
+                .unwrap(),
+            0
+        );
+        tx.commit().await.unwrap();
+
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        assert!(crate::labels::current(&mut db_conn, "did:plc:a")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            crate::labels::current(&mut db_conn, "did:plc:b")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(is_opted_out(&mut db_conn, "did:plc:a").await.unwrap());
+        assert!(!is_opted_out(&mut db_conn, "did:plc:b").await.unwrap());
+
+        let mut tx = db_pool.begin().await.unwrap();
+        assert!(!opt_in(&mut tx, "did:plc:a", "3kother").await.unwrap());
+        assert!(opt_in(&mut tx, "did:plc:a", "3kblock").await.unwrap());
+        tx.commit().await.unwrap();
+        assert!(!is_opted_out(&mut db_conn, "did:plc:a").await.unwrap());
+    }
+}
@@ -0,0 +1,331 @@
+//! The writes a label sync makes to the labeler's repo, journaled so a sync
+//! that fails part-way is finished by the next one instead of leaving the
+//! repo half-updated.
+//!
+//! `chunks` orders a sync's writes creates first, then post updates, then
+//! the labeler service record, then deletes: wherever a sync stops, every
+//! post the published service record points at exists. Each chunk is
+//! applied with `swapCommit` set to the commit the one before it made (the
+//! first, to the repo's head when the sync read it), so a repo that changed
+//! behind the sync's back fails with `InvalidSwap` rather than being written
+//! over.
+//!
+//! The plan and how far it got are kept in `sync_journal`. The next sync
+//! rolls an unfinished journal forward if the repo is still at the commit it
+//! recorded, and abandons it otherwise. Either way, the labels it was to move
+//! (renamed events) or withdraw (removed events) are handed to that sync,
+//! since once the service record is written nothing else remembers them.
+
+/// Writes per `applyWrites` call; the PDS takes at most 200.
+pub const CHUNK_SIZE: usize = 200;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+
+    #[error("serde_json: {0}")]
+    SerdeJson(#[from] serde_json::Error),
+}
+
+/// What a sync does: its writes, in chunks, and the label work that follows
+/// once they are in.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Plan {
+    pub chunks: Vec<Vec<atrium_api::com::atproto::repo::apply_writes::InputWritesItem>>,
+    /// Old label id -> id of the event it was renamed to.
+    pub renamed: std::collections::HashMap<String, String>,
+    pub removed_label_ids: Vec<String>,
+}
+
+/// A plan, how many of its chunks are applied, and the repo commit the last
+/// one left.
+#[derive(Debug)]
+pub struct Journal {
+    pub id: i64,
+    pub plan: Plan,
+    pub applied: usize,
+    pub commit_cid: String,
+    pub abandoned: bool,
+}
+
+/// `writes` in the order they are applied (see the module docs), in chunks
+/// of at most `CHUNK_SIZE`.
+pub fn chunks(
+    mut writes: Vec<atrium_api::com::atproto::repo::apply_writes::InputWritesItem>,
+) -> Vec<Vec<atrium_api::com::atproto::repo::apply_writes::InputWritesItem>> {
+    use atrium_api::com::atproto::repo::apply_writes::InputWritesItem;
+    use atrium_api::types::Collection as _;
+
+    writes.sort_by_key(|write| match write {
+        InputWritesItem::Create(_) => 0,
+        InputWritesItem::Update(update)
+            if update.collection.as_str() == atrium_api::app::bsky::labeler::Service::NSID =>
+        {
+            2
+        }
+        InputWritesItem::Update(_) => 1,
+        InputWritesItem::Delete(_) => 3,
+    });
+    writes
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+/// The journal of the last sync, unless that sync finished. An abandoned one
+/// still has label work to hand on.
+pub async fn unfinished(db_conn: &mut sqlx::PgConnection) -> Result<Option<Journal>, Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+        SELECT id, plan, applied, commit_cid, abandoned
+        FROM sync_journal
+        WHERE finished_at IS NULL
+        ORDER BY id DESC
+        LIMIT 1
+        "#
+    )
+    .fetch_optional(&mut *db_conn)
+    .await?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(Journal {
+        id: row.id,
+        plan: serde_json::from_str(&row.plan)?,
+        applied: row.applied as usize,
+        commit_cid: row.commit_cid,
+        abandoned: row.abandoned,
+    }))
+}
+
+/// Journal `plan`, to be applied on top of `commit_cid`. Earlier unfinished
+/// journals are marked finished: their label work is in `plan` now. Ones
+/// already finished are deleted, so the table keeps the last sync or two to
+/// look at rather than every plan ever made.
+pub async fn begin(
+    db_conn: &mut sqlx::PgConnection,
+    plan: Plan,
+    commit_cid: String,
+) -> Result<Journal, Error> {
+    use sqlx::Acquire as _;
+
+    let mut tx = db_conn.begin().await?;
+    sqlx::query!(
+        r#"
+        DELETE FROM sync_journal WHERE finished_at IS NOT NULL
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        r#"
+        UPDATE sync_journal SET finished_at = NOW() WHERE finished_at IS NULL
+        "#
+    )
+    .execute(&mut *tx)
+    .await?;
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO sync_journal (plan, commit_cid) VALUES ($1, $2)
+        RETURNING id
+        "#,
+        serde_json::to_string(&plan)?,
+        commit_cid,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(Journal {
+        id,
+        plan,
+        applied: 0,
+        commit_cid,
+        abandoned: false,
+    })
+}
+
+/// Record that `journal.applied` chunks are in, leaving the repo at
+/// `journal.commit_cid`.
+pub async fn advance(db_conn: &mut sqlx::PgConnection, journal: &Journal) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE sync_journal SET applied = $2, commit_cid = $3 WHERE id = $1
+        "#,
+        journal.id,
+        journal.applied as i32,
+        journal.commit_cid,
+    )
+    .execute(&mut *db_conn)
+    .await?;
+    Ok(())
+}
+
+/// Stop applying `journal`'s writes: the repo moved on without it.
+pub async fn abandon(db_conn: &mut sqlx::PgConnection, journal: &mut Journal) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE sync_journal SET abandoned = true WHERE id = $1
+        "#,
+        journal.id,
+    )
+    .execute(&mut *db_conn)
+    .await?;
+    journal.abandoned = true;
+    Ok(())
+}
+
+/// `journal`'s writes and label work are all done.
+pub async fn finish(db_conn: &mut sqlx::PgConnection, journal: &Journal) -> Result<(), Error> {
+    sqlx::query!(
+        r#"
+        UPDATE sync_journal SET finished_at = NOW() WHERE id = $1
+        "#,
+        journal.id,
+    )
+    .execute(&mut *db_conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::com::atproto::repo::apply_writes::{
+        CreateData, DeleteData, InputWritesItem, UpdateData,
+    };
+    use atrium_api::types::{Collection as _, TryIntoUnknown as _};
+
+    fn rkey(s: &str) -> atrium_api::types::string::RecordKey {
+        atrium_api::types::string::RecordKey::new(s.to_string()).unwrap()
+    }
+
+    fn value() -> atrium_api::types::Unknown {
+        serde_json::json!({"text": "x"}).try_into_unknown().unwrap()
+    }
+
+    fn delete(rkey_: &str) -> InputWritesItem {
+        InputWritesItem::Delete(Box::new(
+            DeleteData {
+                collection: atrium_api::app::bsky::feed::Post::nsid(),
+                rkey: rkey(rkey_),
+            }
+            .into(),
+        ))
+    }
+
+    fn create(rkey_: &str) -> InputWritesItem {
+        InputWritesItem::Create(Box::new(
+            CreateData {
+                collection: atrium_api::app::bsky::feed::Post::nsid(),
+                rkey: Some(rkey(rkey_)),
+                value: value(),
+            }
+            .into(),
+        ))
+    }
+
+    fn update(collection: atrium_api::types::string::Nsid, rkey_: &str) -> InputWritesItem {
+        InputWritesItem::Update(Box::new(
+            UpdateData {
+                collection,
+                rkey: rkey(rkey_),
+                value: value(),
+            }
+            .into(),
+        ))
+    }
+
+    fn describe(write: &InputWritesItem) -> String {
+        match write {
+            InputWritesItem::Create(create) => {
+                format!("create {}", create.rkey.as_ref().unwrap().as_str())
+            }
+            InputWritesItem::Update(update) => format!("update {}", update.rkey.as_str()),
+            InputWritesItem::Delete(delete) => format!("delete {}", delete.rkey.as_str()),
+        }
+    }
+
+    #[test]
+    fn service_record_after_creates_before_deletes() {
+        let chunks = chunks(vec![
+            delete("old"),
+            update(atrium_api::app::bsky::feed::Post::nsid(), "kept"),
+            create("new"),
+            update(atrium_api::app::bsky::labeler::Service::nsid(), "self"),
+        ]);
+        assert_eq!(
+            chunks[0].iter().map(describe).collect::<Vec<_>>(),
+            vec!["create new", "update kept", "update self", "delete old"]
+        );
+
+        let chunks = super::chunks((0..CHUNK_SIZE + 1).map(|_| delete("old")).collect());
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            vec![CHUNK_SIZE, 1]
+        );
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // A journal survives the round trip, and a new one takes over from it.
+    #[tokio::test]
+    #[ignore]
+    async fn journal_round_trip() {
+        let db_pool = crate::test_db::pool().await;
+        let mut db_conn = db_pool.acquire().await.unwrap();
+        assert!(unfinished(&mut db_conn).await.unwrap().is_none());
+
+        let mut journal = begin(
+            &mut db_conn,
+            Plan {
+                chunks: super::chunks(vec![create("new"), delete("old")]),
+                renamed: [("ac-mmxxv".to_string(), "anthrocon-2025".to_string())].into(),
+                removed_label_ids: vec!["gone-mmxx".to_string()],
+            },
+            "bafyone".to_string(),
+        )
+        .await
+        .unwrap();
+        journal.applied = 1;
+        journal.commit_cid = "bafytwo".to_string();
+        advance(&mut db_conn, &journal).await.unwrap();
+        abandon(&mut db_conn, &mut journal).await.unwrap();
+
+        let resumed = unfinished(&mut db_conn).await.unwrap().unwrap();
+        assert_eq!(
+            (
+                resumed.id,
+                resumed.applied,
+                resumed.commit_cid.as_str(),
+                resumed.abandoned
+            ),
+            (journal.id, 1, "bafytwo", true)
+        );
+        assert_eq!(
+            resumed.plan.chunks[0]
+                .iter()
+                .map(describe)
+                .collect::<Vec<_>>(),
+            vec!["create new", "delete old"]
+        );
+        assert_eq!(resumed.plan.renamed, journal.plan.renamed);
+        assert_eq!(resumed.plan.removed_label_ids, vec!["gone-mmxx"]);
+
+        let next = begin(&mut db_conn, Plan::default(), "bafythree".to_string())
+            .await
+            .unwrap();
+        assert_eq!(unfinished(&mut db_conn).await.unwrap().unwrap().id, next.id);
+        finish(&mut db_conn, &next).await.unwrap();
+        assert!(unfinished(&mut db_conn).await.unwrap().is_none());
+
+        // Both finished journals go when the one after them starts.
+        let last = begin(&mut db_conn, Plan::default(), "bafyfour".to_string())
+            .await
+            .unwrap();
+        let ids: Vec<i64> = sqlx::query_scalar("SELECT id FROM sync_journal ORDER BY id")
+            .fetch_all(&mut *db_conn)
+            .await
+            .unwrap();
+        assert_eq!(ids, vec![last.id]);
+    }
+}
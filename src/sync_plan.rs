@@ -0,0 +1,534 @@
+//! What a label sync would do, without doing it: the posts (and threadgates)
+//! it would create, update and delete, the label definitions it would add,
+//! remove and change, the labels it would move or withdraw, and how the
+//! con-post watchlist would change. Worked out against the live repo the way
+//! a sync does, minus `applyWrites`; see `--plan` and `GET /plan`.
+
+/// How a plan is printed: `text` for people, `json` for tools.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Plan {
+    pub posts: PostChanges,
+    pub threadgates: ThreadgateChanges,
+    pub definitions: DefinitionChanges,
+    pub relabeled: Vec<Relabel>,
+    pub negated: Vec<Negation>,
+    pub watchlist: WatchlistChanges,
+    /// A sync that stopped part-way; the next one applies the rest of its
+    /// writes first, which this plan doesn't include.
+    pub unfinished_journal: Option<JournalStatus>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct PostChanges {
+    pub created: Vec<Post>,
+    pub updated: Vec<PostUpdate>,
+    pub deleted: Vec<Post>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Post {
+    pub rkey: String,
+    pub event_id: Option<String>,
+    pub text: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct PostUpdate {
+    pub rkey: String,
+    pub event_id: Option<String>,
+    pub changes: Vec<&'static str>,
+    pub text: String,
+}
+
+/// Threadgate rkeys (the same as their post's).
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ThreadgateChanges {
+    pub created: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Label definitions on the labeler service record, by identifier.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct DefinitionChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<DefinitionChange>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct DefinitionChange {
+    pub identifier: String,
+    /// The fields that differ, as in the record, e.g. `locales`.
+    pub fields: Vec<String>,
+}
+
+/// Current labels of a renamed event moving to its new label.
+#[derive(Debug, serde::Serialize)]
+pub struct Relabel {
+    pub from: String,
+    pub to: String,
+    pub labels: usize,
+}
+
+/// Current labels of an event gone from the feed, to be negated.
+#[derive(Debug, serde::Serialize)]
+pub struct Negation {
+    pub label_id: String,
+    pub labels: usize,
+}
+
+/// Con accounts (did -> series id) watched for key-date posts. Without a
+/// running labeler (`--plan`) there is no watchlist to compare with, and
+/// only `size` is known.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct WatchlistChanges {
+    pub compared: bool,
+    pub added: std::collections::BTreeMap<String, String>,
+    pub removed: std::collections::BTreeMap<String, String>,
+    pub changed: std::collections::BTreeMap<String, (String, String)>,
+    pub size: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct JournalStatus {
+    pub id: i64,
+    pub applied: usize,
+    pub chunks: usize,
+    pub abandoned: bool,
+}
+
+impl Plan {
+    /// The post, threadgate and definition parts of a plan, from a sync's
+    /// `writes`. `existing` is the repo's posts by rkey, `post_events` the
+    /// event id of each post rkey (kept, new or going), `old_definitions`
+    /// those on the service record now.
+    pub fn new(
+        writes: &[atrium_api::com::atproto::repo::apply_writes::InputWritesItem],
+        existing: &std::collections::HashMap<
+            atrium_api::types::string::RecordKey,
+            atrium_api::types::Unknown,
+        >,
+        post_events: &std::collections::HashMap<String, String>,
+        old_definitions: &[atrium_api::com::atproto::label::defs::LabelValueDefinition],
+    ) -> Self {
+        use atrium_api::com::atproto::repo::apply_writes::InputWritesItem;
+        use atrium_api::types::{Collection as _, TryFromUnknown as _};
+
+        let post = |value: Option<&atrium_api::types::Unknown>| {
+            value.cloned().and_then(|value| {
+                atrium_api::app::bsky::feed::post::Record::try_from_unknown(value).ok()
+            })
+        };
+
+        let mut plan = Plan::default();
+        for write in writes {
+            match write {
+                InputWritesItem::Create(create) => {
+                    let rkey = create
+                        .rkey
+                        .as_ref()
+                        .map(|rkey| rkey.as_str().to_string())
+                        .unwrap_or_default();
+                    match create.collection.as_str() {
+                        atrium_api::app::bsky::feed::Post::NSID => plan.posts.created.push(Post {
+                            event_id: post_events.get(&rkey).cloned(),
+                            text: post(Some(&create.value)).map(|record| record.data.text),
+                            rkey,
+                        }),
+                        atrium_api::app::bsky::feed::Threadgate::NSID => {
+                            plan.threadgates.created.push(rkey)
+                        }
+                        _ => {}
+                    }
+                }
+                InputWritesItem::Update(update) => match update.collection.as_str() {
+                    atrium_api::app::bsky::feed::Post::NSID => {
+                        let (Some(existing), Some(wanted)) =
+                            (post(existing.get(&update.rkey)), post(Some(&update.value)))
+                        else {
+                            continue;
+                        };
+                        plan.posts.updated.push(PostUpdate {
+                            rkey: update.rkey.as_str().to_string(),
+                            event_id: post_events.get(update.rkey.as_str()).cloned(),
+                            changes: crate::event_posts::changes(&existing, &wanted),
+                            text: wanted.data.text,
+                        });
+                    }
+                    atrium_api::app::bsky::labeler::Service::NSID => {
+                        if let Ok(record) =
+                            atrium_api::app::bsky::labeler::service::Record::try_from_unknown(
+                                update.value.clone(),
+                            )
+                        {
+                            plan.definitions = definition_changes(
+                                old_definitions,
+                                record
+                                    .data
+                                    .policies
+                                    .data
+                                    .label_value_definitions
+                                    .as_deref()
+                                    .unwrap_or_default(),
+                            );
+                        }
+                    }
+                    _ => {}
+                },
+                InputWritesItem::Delete(delete) => {
+                    let rkey = delete.rkey.as_str().to_string();
+                    match delete.collection.as_str() {
+                        atrium_api::app::bsky::feed::Post::NSID => plan.posts.deleted.push(Post {
+                            event_id: post_events.get(&rkey).cloned(),
+                            text: post(existing.get(&delete.rkey)).map(|record| record.data.text),
+                            rkey,
+                        }),
+                        atrium_api::app::bsky::feed::Threadgate::NSID => {
+                            plan.threadgates.deleted.push(rkey)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        plan
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.posts.created.is_empty()
+            && self.posts.updated.is_empty()
+            && self.posts.deleted.is_empty()
+            && self.threadgates.created.is_empty()
+            && self.threadgates.deleted.is_empty()
+            && self.definitions.added.is_empty()
+            && self.definitions.removed.is_empty()
+            && self.definitions.changed.is_empty()
+            && self.relabeled.is_empty()
+            && self.negated.is_empty()
+            && self.watchlist.added.is_empty()
+            && self.watchlist.removed.is_empty()
+            && self.watchlist.changed.is_empty()
+    }
+}
+
+/// Definitions added, removed and changed going from `old` to `new`. A
+/// change lists the record fields that differ, extra data included.
+pub fn definition_changes(
+    old: &[atrium_api::com::atproto::label::defs::LabelValueDefinition],
+    new: &[atrium_api::com::atproto::label::defs::LabelValueDefinition],
+) -> DefinitionChanges {
+    let by_identifier = |defs: &[atrium_api::com::atproto::label::defs::LabelValueDefinition]| {
+        defs.iter()
+            .map(|def| {
+                let serde_json::Value::Object(fields) =
+                    serde_json::to_value(def).unwrap_or_default()
+                else {
+                    unreachable!()
+                };
+                (def.identifier.clone(), fields)
+            })
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+    let old = by_identifier(old);
+    let new = by_identifier(new);
+
+    let mut changes = DefinitionChanges {
+        added: new
+            .keys()
+            .filter(|identifier| !old.contains_key(*identifier))
+            .cloned()
+            .collect(),
+        removed: old
+            .keys()
+            .filter(|identifier| !new.contains_key(*identifier))
+            .cloned()
+            .collect(),
+        changed: vec![],
+    };
+    for (identifier, new_fields) in &new {
+        let Some(old_fields) = old.get(identifier) else {
+            continue;
+        };
+        let fields = old_fields
+            .keys()
+            .chain(
+                new_fields
+                    .keys()
+                    .filter(|key| !old_fields.contains_key(*key)),
+            )
+            .filter(|key| old_fields.get(*key) != new_fields.get(*key))
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>();
+        if !fields.is_empty() {
+            changes.changed.push(DefinitionChange {
+                identifier: identifier.clone(),
+                fields: fields.into_iter().collect(),
+            });
+        }
+    }
+    changes
+}
+
+/// How the watchlist goes from `old` (`None` if there is none to compare
+/// with) to `new`.
+pub fn watchlist_changes(
+    old: Option<&std::collections::HashMap<String, String>>,
+    new: &std::collections::HashMap<String, String>,
+) -> WatchlistChanges {
+    let mut changes = WatchlistChanges {
+        compared: old.is_some(),
+        size: new.len(),
+        ..Default::default()
+    };
+    let Some(old) = old else {
+        return changes;
+    };
+    for (did, series_id) in new {
+        match old.get(did) {
+            None => {
+                changes.added.insert(did.clone(), series_id.clone());
+            }
+            Some(old_series_id) if old_series_id != series_id => {
+                changes
+                    .changed
+                    .insert(did.clone(), (old_series_id.clone(), series_id.clone()));
+            }
+            Some(_) => {}
+        }
+    }
+    for (did, series_id) in old {
+        if !new.contains_key(did) {
+            changes.removed.insert(did.clone(), series_id.clone());
+        }
+    }
+    changes
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn post(f: &mut std::fmt::Formatter<'_>, sign: char, post: &Post) -> std::fmt::Result {
+            writeln!(
+                f,
+                "  {sign} {} {}: {:?}",
+                post.rkey,
+                post.event_id.as_deref().unwrap_or("?"),
+                post.text.as_deref().unwrap_or("?")
+            )
+        }
+
+        if let Some(journal) = &self.unfinished_journal {
+            writeln!(
+                f,
+                "sync journal {} stopped after {}/{} chunk(s){}; not included below",
+                journal.id,
+                journal.applied,
+                journal.chunks,
+                if journal.abandoned {
+                    " and was abandoned"
+                } else {
+                    ", the next sync applies the rest first"
+                }
+            )?;
+        }
+        if self.is_empty() {
+            writeln!(f, "nothing to do")?;
+        }
+
+        let posts = &self.posts;
+        if !(posts.created.is_empty() && posts.updated.is_empty() && posts.deleted.is_empty()) {
+            writeln!(
+                f,
+                "posts: {} to create, {} to update, {} to delete",
+                posts.created.len(),
+                posts.updated.len(),
+                posts.deleted.len()
+            )?;
+            for created in &posts.created {
+                post(f, '+', created)?;
+            }
+            for updated in &posts.updated {
+                writeln!(
+                    f,
+                    "  ~ {} {} ({}): {:?}",
+                    updated.rkey,
+                    updated.event_id.as_deref().unwrap_or("?"),
+                    updated.changes.join(", "),
+                    updated.text
+                )?;
+            }
+            for deleted in &posts.deleted {
+                post(f, '-', deleted)?;
+            }
+        }
+
+        if !(self.threadgates.created.is_empty() && self.threadgates.deleted.is_empty()) {
+            writeln!(
+                f,
+                "threadgates: {} to create, {} to delete",
+                self.threadgates.created.len(),
+                self.threadgates.deleted.len()
+            )?;
+        }
+
+        let definitions = &self.definitions;
+        if !(definitions.added.is_empty()
+            && definitions.removed.is_empty()
+            && definitions.changed.is_empty())
+        {
+            writeln!(
+                f,
+                "label definitions: {} to add, {} to remove, {} to change",
+                definitions.added.len(),
+                definitions.removed.len(),
+                definitions.changed.len()
+            )?;
+            for identifier in &definitions.added {
+                writeln!(f, "  + {identifier}")?;
+            }
+            for change in &definitions.changed {
+                writeln!(
+                    f,
+                    "  ~ {} ({})",
+                    change.identifier,
+                    change.fields.join(", ")
+                )?;
+            }
+            for identifier in &definitions.removed {
+                writeln!(f, "  - {identifier}")?;
+            }
+        }
+
+        if !(self.relabeled.is_empty() && self.negated.is_empty()) {
+            writeln!(f, "labels:")?;
+            for relabel in &self.relabeled {
+                writeln!(
+                    f,
+                    "  {} -> {}: {} label(s) to move",
+                    relabel.from, relabel.to, relabel.labels
+                )?;
+            }
+            for negation in &self.negated {
+                writeln!(
+                    f,
+                    "  - {}: {} label(s) to negate",
+                    negation.label_id, negation.labels
+                )?;
+            }
+        }
+
+        let watchlist = &self.watchlist;
+        if !watchlist.compared {
+            writeln!(
+                f,
+                "watchlist: {} con account(s); no running labeler to compare with",
+                watchlist.size
+            )?;
+        } else if !(watchlist.added.is_empty()
+            && watchlist.removed.is_empty()
+            && watchlist.changed.is_empty())
+        {
+            writeln!(
+                f,
+                "watchlist: {} to add, {} to remove, {} to change ({} con account(s) after)",
+                watchlist.added.len(),
+                watchlist.removed.len(),
+                watchlist.changed.len(),
+                watchlist.size
+            )?;
+            for (did, series_id) in &watchlist.added {
+                writeln!(f, "  + {did} ({series_id})")?;
+            }
+            for (did, (old, new)) in &watchlist.changed {
+                writeln!(f, "  ~ {did} ({old} -> {new})")?;
+            }
+            for (did, series_id) in &watchlist.removed {
+                writeln!(f, "  - {did} ({series_id})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn def(
+        identifier: &str,
+        severity: &str,
+        post_rkey: Option<&str>,
+    ) -> atrium_api::com::atproto::label::defs::LabelValueDefinition {
+        serde_json::from_value(serde_json::json!({
+            "identifier": identifier,
+            "blurs": "none",
+            "severity": severity,
+            "locales": [{"lang": "en", "name": identifier, "description": ""}],
+            "fbl_postRkey": post_rkey,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn definitions_and_watchlist_diff() {
+        let changes = definition_changes(
+            &[
+                def("ac-mmxxv", "inform", Some("3kac")),
+                def("gone-mmxx", "inform", Some("3kgone")),
+                def("same-mmxxv", "inform", None),
+            ],
+            &[
+                def("ac-mmxxv", "alert", None),
+                def("new-mmxxv", "inform", Some("3knew")),
+                def("same-mmxxv", "inform", None),
+            ],
+        );
+        assert_eq!(changes.added, vec!["new-mmxxv"]);
+        assert_eq!(changes.removed, vec!["gone-mmxx"]);
+        assert_eq!(
+            changes
+                .changed
+                .iter()
+                .map(|change| (change.identifier.as_str(), change.fields.clone()))
+                .collect::<Vec<_>>(),
+            vec![(
+                "ac-mmxxv",
+                vec!["fbl_postRkey".to_string(), "severity".to_string()]
+            )]
+        );
+
+        let old = [("did:plc:ac", "ac"), ("did:plc:gone", "gone")]
+            .into_iter()
+            .map(|(did, series_id)| (did.to_string(), series_id.to_string()))
+            .collect();
+        let new = [("did:plc:ac", "anthrocon"), ("did:plc:mff", "mff")]
+            .into_iter()
+            .map(|(did, series_id)| (did.to_string(), series_id.to_string()))
+            .collect();
+        let mut plan = Plan {
+            watchlist: watchlist_changes(Some(&old), &new),
+            ..Default::default()
+        };
+        assert_eq!(
+            plan.to_string(),
+            "watchlist: 1 to add, 1 to remove, 1 to change (2 con account(s) after)\n  \
+             + did:plc:mff (mff)\n  \
+             ~ did:plc:ac (ac -> anthrocon)\n  \
+             - did:plc:gone (gone)\n"
+        );
+
+        plan.watchlist = watchlist_changes(None, &new);
+        assert!(plan.is_empty());
+        assert_eq!(
+            plan.to_string(),
+            "nothing to do\nwatchlist: 2 con account(s); no running labeler to compare with\n"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Scratch Postgres for the DB-backed tests. Those tests are `#[ignore]`d
+//! (CI's build job has no database); run them against a local server with
+//! `DATABASE_URL=postgres://… cargo test -- --ignored`. Each call gets its
+//! own schema with `schema.sql` applied, so tests can run in parallel and
+//! never see each other's rows.
+
+static NEXT: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+pub async fn pool() -> sqlx::PgPool {
+    let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a scratch database");
+    let schema = format!(
+        "test_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+    );
+
+    let admin = sqlx::PgPool::connect(&url).await.unwrap();
+    sqlx::raw_sql(&format!(
+        "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}"
+    ))
+    .execute(&admin)
+    .await
+    .unwrap();
+    admin.close().await;
+
+    let options = url
+        .parse::<sqlx::postgres::PgConnectOptions>()
+        .unwrap()
+        .options([("search_path", schema.as_str())]);
+    let db_pool = sqlx::PgPool::connect_with(options).await.unwrap();
+    sqlx::raw_sql(include_str!("../schema.sql"))
+        .execute(&db_pool)
+        .await
+        .unwrap();
+    db_pool
+}
+
+/// A fixed signing key, so signatures are reproducible across runs.
+pub fn keypair() -> atrium_crypto::keypair::Secp256k1Keypair {
+    atrium_crypto::keypair::Secp256k1Keypair::import(&[1; 32]).unwrap()
+}
@@ -0,0 +1,34 @@
+//! The labeler's XRPC surface, served from the ingester's own axum server
+//! alongside `/trigger`. Everything here reads the `labels` table that
+//! `labels::emit` writes; nothing here writes labels.
+
+pub mod query_labels;
+pub mod subscribe_labels;
+
+#[derive(Clone)]
+pub struct State {
+    pub db_pool: sqlx::PgPool,
+    /// The labeler's own DID: the only label source this server has.
+    pub did: atrium_api::types::string::Did,
+    /// Bumped on every `NOTIFY labels`; see `subscribe_labels::watch`.
+    pub new_labels: tokio::sync::watch::Receiver<()>,
+}
+
+pub fn router(state: State) -> axum::Router {
+    axum::Router::new()
+        .route(
+            &format!(
+                "/xrpc/{}",
+                atrium_api::com::atproto::label::query_labels::NSID
+            ),
+            axum::routing::get(query_labels::handler),
+        )
+        .route(
+            &format!(
+                "/xrpc/{}",
+                atrium_api::com::atproto::label::subscribe_labels::NSID
+            ),
+            axum::routing::get(subscribe_labels::handler),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,307 @@
+//! `com.atproto.label.queryLabels`: page through `labels` by seq for a set of
+//! subject URI patterns, returning the stored signed labels. As an operator
+//! convenience, an exact pattern that is a bare handle (no scheme) stands for
+//! the DID the handle cache has for it; see `handles`.
+
+const DEFAULT_LIMIT: i64 = 50;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+
+    #[error("serde_ipld_dagcbor decode: {0}")]
+    Decode(#[from] serde_ipld_dagcbor::DecodeError<std::convert::Infallible>),
+}
+
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        let (status, error) = match &self {
+            Error::InvalidRequest(_) => (axum::http::StatusCode::BAD_REQUEST, "InvalidRequest"),
+            _ => {
+                log::error!("queryLabels: {self}");
+                (
+                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                    "InternalServerError",
+                )
+            }
+        };
+        (
+            status,
+            axum::Json(serde_json::json!({ "error": error, "message": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+/// `uriPatterns` split into exact URIs and `LIKE` prefixes. Only a trailing
+/// `*` is a wildcard; anywhere else it is rejected, as in the lexicon.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Patterns {
+    exact: Vec<String>,
+    prefixes: Vec<String>,
+}
+
+impl Patterns {
+    fn parse(uri_patterns: &[String]) -> Result<Self, Error> {
+        let mut patterns = Self::default();
+        for pattern in uri_patterns {
+            match pattern.strip_suffix('*') {
+                Some(prefix) if !prefix.contains('*') => {
+                    let mut like = prefix
+                        .replace('\\', "\\\\")
+                        .replace('%', "\\%")
+                        .replace('_', "\\_");
+                    like.push('%');
+                    patterns.prefixes.push(like);
+                }
+                None if !pattern.contains('*') => patterns.exact.push(pattern.clone()),
+                _ => {
+                    return Err(Error::InvalidRequest(format!(
+                        "only a trailing * is allowed: {pattern:?}"
+                    )))
+                }
+            }
+        }
+        Ok(patterns)
+    }
+}
+
+pub async fn handler(
+    axum::extract::State(state): axum::extract::State<super::State>,
+    axum_extra::extract::Query(params): axum_extra::extract::Query<
+        atrium_api::com::atproto::label::query_labels::ParametersData,
+    >,
+) -> Result<axum::Json<atrium_api::com::atproto::label::query_labels::OutputData>, Error> {
+    Ok(axum::Json(
+        query(&state.db_pool, &state.did, &params).await?,
+    ))
+}
+
+pub async fn query(
+    db_pool: &sqlx::PgPool,
+    did: &atrium_api::types::string::Did,
+    params: &atrium_api::com::atproto::label::query_labels::ParametersData,
+) -> Result<atrium_api::com::atproto::label::query_labels::OutputData, Error> {
+    let patterns = Patterns::parse(&params.uri_patterns)?;
+
+    let cursor = params
+        .cursor
+        .as_deref()
+        .map(|cursor| {
+            cursor
+                .parse::<i64>()
+                .map_err(|_| Error::InvalidRequest(format!("bad cursor: {cursor:?}")))
+        })
+        .transpose()?
+        .unwrap_or(0);
+
+    let limit = params
+        .limit
+        .map(|limit| u8::from(limit) as i64)
+        .unwrap_or(DEFAULT_LIMIT);
+
+    // Every row here is ours; asking only for other sources matches nothing.
+    if params
+        .sources
+        .as_ref()
+        .is_some_and(|sources| !sources.is_empty() && !sources.contains(did))
+    {
+        return Ok(atrium_api::com::atproto::label::query_labels::OutputData {
+            cursor: None,
+            labels: vec![],
+        });
+    }
+
+    let mut db_conn = db_pool.acquire().await?;
+
+    let mut exact = Vec::with_capacity(patterns.exact.len());
+    for pattern in patterns.exact {
+        if pattern.contains(':') {
+            exact.push(pattern);
+        } else if let Some(did) = crate::handles::did(&mut db_conn, &pattern).await? {
+            exact.push(did);
+        }
+    }
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT seq, payload
+        FROM labels
+        WHERE seq > $1 AND (uri = ANY($2) OR uri LIKE ANY($3))
+        ORDER BY seq
+        LIMIT $4
+        "#,
+        cursor,
+        &exact,
+        &patterns.prefixes,
+        limit,
+    )
+    .fetch_all(&mut *db_conn)
+    .await?;
+
+    // A short page is the last one.
+    let next_cursor = (rows.len() as i64 == limit)
+        .then(|| rows.last().map(|row| row.seq.to_string()))
+        .flatten();
+
+    Ok(atrium_api::com::atproto::label::query_labels::OutputData {
+        cursor: next_cursor,
+        labels: rows
+            .iter()
+            .map(|row| serde_ipld_dagcbor::from_slice(&row.payload))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(
+        uri_patterns: &[&str],
+        sources: Option<&[&str]>,
+        limit: Option<u8>,
+        cursor: Option<&str>,
+    ) -> atrium_api::com::atproto::label::query_labels::ParametersData {
+        atrium_api::com::atproto::label::query_labels::ParametersData {
+            uri_patterns: uri_patterns.iter().map(|p| p.to_string()).collect(),
+            sources: sources.map(|sources| {
+                sources
+                    .iter()
+                    .map(|s| atrium_api::types::string::Did::new(s.to_string()).unwrap())
+                    .collect()
+            }),
+            limit: limit.map(|limit| limit.try_into().unwrap()),
+            cursor: cursor.map(|c| c.to_string()),
+        }
+    }
+
+    #[test]
+    fn trailing_star_is_an_escaped_prefix() {
+        let patterns = Patterns::parse(&[
+            "did:plc:abc".to_string(),
+            "at://did:plc:abc/*".to_string(),
+            "100%_\\*".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(patterns.exact, vec!["did:plc:abc"]);
+        assert_eq!(
+            patterns.prefixes,
+            vec!["at://did:plc:abc/%", "100\\%\\_\\\\%"]
+        );
+    }
+
+    #[test]
+    fn inner_star_is_rejected() {
+        assert!(matches!(
+            Patterns::parse(&["did:*:abc".to_string()]),
+            Err(Error::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            Patterns::parse(&["did:plc:**".to_string()]),
+            Err(Error::InvalidRequest(_))
+        ));
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn filters_and_paginates() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let did = atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap();
+
+        for uri in ["did:plc:aaa", "did:plc:aab", "did:plc:b_c", "did:plc:bxc"] {
+            let label = atrium_api::com::atproto::label::defs::LabelData {
+                cts: atrium_api::types::string::Datetime::now(),
+                exp: None,
+                src: did.clone(),
+                cid: None,
+                neg: None,
+                uri: uri.to_string(),
+                val: "examplecon-mmxxv".to_string(),
+                sig: None,
+                ver: Some(1),
+            }
+            .into();
+            let mut tx = db_pool.begin().await.unwrap();
+            crate::labels::emit(&keypair, &mut tx, &label, "3kabc")
+                .await
+                .unwrap()
+                .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        let uris = |output: &atrium_api::com::atproto::label::query_labels::OutputData| {
+            output
+                .labels
+                .iter()
+                .map(|l| l.uri.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let exact = query(&db_pool, &did, &params(&["did:plc:aab"], None, None, None))
+            .await
+            .unwrap();
+        assert_eq!(uris(&exact), vec!["did:plc:aab"]);
+        assert!(exact.labels[0].sig.is_some());
+        assert_eq!(exact.cursor, None);
+
+        crate::handles::record(
+            &mut db_pool.acquire().await.unwrap(),
+            "did:plc:aaa",
+            Some("alice.bsky.social"),
+            chrono::Utc::now(),
+        )
+        .await
+        .unwrap();
+        let by_handle = query(
+            &db_pool,
+            &did,
+            &params(&["alice.bsky.social", "bob.bsky.social"], None, None, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(uris(&by_handle), vec!["did:plc:aaa"]);
+
+        // `_` in a prefix is literal, not LIKE's any-character.
+        let prefix = query(&db_pool, &did, &params(&["did:plc:b_*"], None, None, None))
+            .await
+            .unwrap();
+        assert_eq!(uris(&prefix), vec!["did:plc:b_c"]);
+
+        let other_source = query(
+            &db_pool,
+            &did,
+            &params(&["did:plc:*"], Some(&["did:plc:someoneelse"]), None, None),
+        )
+        .await
+        .unwrap();
+        assert!(other_source.labels.is_empty());
+
+        let first = query(
+            &db_pool,
+            &did,
+            &params(&["did:plc:*"], Some(&["did:plc:labeler"]), Some(3), None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            uris(&first),
+            vec!["did:plc:aaa", "did:plc:aab", "did:plc:b_c"]
+        );
+        let rest = query(
+            &db_pool,
+            &did,
+            &params(&["did:plc:*"], None, Some(3), first.cursor.as_deref()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(uris(&rest), vec!["did:plc:bxc"]);
+        assert_eq!(rest.cursor, None);
+    }
+}
@@ -0,0 +1,425 @@
+//! `com.atproto.label.subscribeLabels`: replay `labels` from a cursor, then
+//! stream new rows as `labels::emit` commits them.
+//!
+//! Each WebSocket message is an event-stream frame: a DAG-CBOR header
+//! (`{op: 1, t: "#labels"}`, or `{op: -1}` for an error) immediately followed
+//! by a DAG-CBOR body. Rows carry the already-signed label as `payload`, so
+//! the body is just that label re-wrapped with its seq.
+
+use futures::StreamExt as _;
+
+/// Rows read per query while replaying, so a `cursor=0` subscriber doesn't
+/// pull the whole table into memory at once.
+const PAGE_SIZE: i64 = 500;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("sqlx: {0}")]
+    Sqlx(#[from] sqlx::Error),
+
+    #[error("axum: {0}")]
+    Axum(#[from] axum::Error),
+
+    #[error("serde_ipld_dagcbor encode: {0}")]
+    Encode(#[from] serde_ipld_dagcbor::EncodeError<std::collections::TryReserveError>),
+
+    #[error("serde_ipld_dagcbor decode: {0}")]
+    Decode(#[from] serde_ipld_dagcbor::DecodeError<std::convert::Infallible>),
+}
+
+#[derive(serde::Serialize)]
+struct Header<'a> {
+    op: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    t: Option<&'a str>,
+}
+
+fn frame(header: &Header<'_>, body: &impl serde::Serialize) -> Result<Vec<u8>, Error> {
+    let mut buf = serde_ipld_dagcbor::to_vec(header)?;
+    buf.extend(serde_ipld_dagcbor::to_vec(body)?);
+    Ok(buf)
+}
+
+fn labels_frame(seq: i64, payload: &[u8]) -> Result<Vec<u8>, Error> {
+    frame(
+        &Header {
+            op: 1,
+            t: Some("#labels"),
+        },
+        &atrium_api::com::atproto::label::subscribe_labels::LabelsData {
+            seq,
+            labels: vec![serde_ipld_dagcbor::from_slice(payload)?],
+        },
+    )
+}
+
+fn info_frame(name: &str, message: Option<String>) -> Result<Vec<u8>, Error> {
+    frame(
+        &Header {
+            op: 1,
+            t: Some("#info"),
+        },
+        &atrium_api::com::atproto::label::subscribe_labels::InfoData {
+            name: name.to_string(),
+            message,
+        },
+    )
+}
+
+fn error_frame(
+    error: &atrium_api::com::atproto::label::subscribe_labels::Error,
+) -> Result<Vec<u8>, Error> {
+    frame(&Header { op: -1, t: None }, error)
+}
+
+/// Turns `NOTIFY labels` into wakeups on `tx`. One LISTEN connection shared
+/// by every subscriber, instead of each socket pinning a pool connection for
+/// its lifetime. Subscribers re-query by seq on each wakeup, so coalesced or
+/// lost notifications only delay them.
+pub async fn watch(
+    db_pool: &sqlx::PgPool,
+    tx: tokio::sync::watch::Sender<()>,
+) -> Result<(), sqlx::Error> {
+    let mut listener = sqlx::postgres::PgListener::connect_with(db_pool).await?;
+    listener.listen("labels").await?;
+    loop {
+        // `None` is a dropped LISTEN connection, already reconnected: anything
+        // NOTIFYed in between is gone, so wake everyone to re-query anyway.
+        if listener.try_recv().await?.is_none() {
+            log::warn!("subscribeLabels: lost the LISTEN connection, reconnected");
+        }
+        tx.send_replace(());
+    }
+}
+
+pub async fn handler(
+    ws: axum::extract::ws::WebSocketUpgrade,
+    axum::extract::State(state): axum::extract::State<super::State>,
+    axum::extract::Query(params): axum::extract::Query<
+        atrium_api::com::atproto::label::subscribe_labels::ParametersData,
+    >,
+) -> axum::response::Response {
+    ws.on_upgrade(move |socket| async move {
+        if let Err(e) = serve(state, socket, params.cursor).await {
+            log::warn!("subscribeLabels: subscriber dropped: {e}");
+        }
+    })
+}
+
+async fn serve(
+    state: super::State,
+    mut socket: axum::extract::ws::WebSocket,
+    cursor: Option<i64>,
+) -> Result<(), Error> {
+    // Mark before reading the head, so a row committed between the head read
+    // and the first wait still wakes us.
+    let mut new_labels = state.new_labels.clone();
+    new_labels.mark_unchanged();
+
+    let mut db_conn = state.db_pool.acquire().await?;
+
+    // Compaction may have deleted the newest rows too; the horizon keeps the
+    // head from moving back.
+    let (head, horizon) = sqlx::query!(
+        r#"
+        SELECT
+            GREATEST(
+                (SELECT MAX(seq) FROM labels),
+                (SELECT horizon FROM labels_compaction),
+                0
+            ) AS "head!",
+            COALESCE((SELECT horizon FROM labels_compaction), 0) AS "horizon!"
+        "#
+    )
+    .fetch_one(&mut *db_conn)
+    .await
+    .map(|row| (row.head, row.horizon))?;
+
+    let mut last_seq = match cursor {
+        Some(cursor) if cursor > head => {
+            socket
+                .send(axum::extract::ws::Message::Binary(
+                    error_frame(
+                        &atrium_api::com::atproto::label::subscribe_labels::Error::FutureCursor(
+                            Some(format!("cursor {cursor} is ahead of the stream ({head})")),
+                        ),
+                    )?
+                    .into(),
+                ))
+                .await?;
+            socket.send(axum::extract::ws::Message::Close(None)).await?;
+            return Ok(());
+        }
+        Some(cursor) => {
+            // Rows up to the horizon may have been compacted away from under
+            // this cursor; say so, then replay what remains.
+            if cursor < horizon {
+                socket
+                    .send(axum::extract::ws::Message::Binary(
+                        info_frame(
+                            "OutdatedCursor",
+                            Some(format!(
+                                "labels up to seq {horizon} may have been compacted"
+                            )),
+                        )?
+                        .into(),
+                    ))
+                    .await?;
+            }
+            cursor
+        }
+        None => head,
+    };
+
+    // The pool connection is only needed while catching up; don't pin one
+    // for a subscriber that mostly waits.
+    drop(db_conn);
+
+    loop {
+        loop {
+            let mut db_conn = state.db_pool.acquire().await?;
+            let rows = sqlx::query!(
+                r#"
+                SELECT seq, payload
+                FROM labels
+                WHERE seq > $1
+                ORDER BY seq
+                LIMIT $2
+                "#,
+                last_seq,
+                PAGE_SIZE,
+            )
+            .fetch_all(&mut *db_conn)
+            .await?;
+            drop(db_conn);
+
+            let n = rows.len();
+            for row in rows {
+                socket
+                    .send(axum::extract::ws::Message::Binary(
+                        labels_frame(row.seq, &row.payload)?.into(),
+                    ))
+                    .await?;
+                last_seq = row.seq;
+            }
+            if (n as i64) < PAGE_SIZE {
+                break;
+            }
+        }
+
+        tokio::select! {
+            changed = new_labels.changed() => {
+                if changed.is_err() {
+                    // The watcher is gone (shutting down).
+                    return Ok(());
+                }
+            }
+            message = socket.next() => match message {
+                None | Some(Err(_)) | Some(Ok(axum::extract::ws::Message::Close(_))) => {
+                    return Ok(());
+                }
+                // Subscribers have nothing to say; pings are answered by axum.
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn label(
+        did: &str,
+        uri: &str,
+        val: &str,
+        neg: bool,
+    ) -> atrium_api::com::atproto::label::defs::Label {
+        atrium_api::com::atproto::label::defs::LabelData {
+            cts: atrium_api::types::string::Datetime::now(),
+            exp: None,
+            src: atrium_api::types::string::Did::new(did.to_string()).unwrap(),
+            cid: None,
+            neg: neg.then_some(true),
+            uri: uri.to_string(),
+            val: val.to_string(),
+            sig: None,
+            ver: Some(1),
+        }
+        .into()
+    }
+
+    /// Splits a frame back into its header and body.
+    fn unframe<B: serde::de::DeserializeOwned>(mut bytes: &[u8]) -> (ipld_core::ipld::Ipld, B) {
+        let header = serde_ipld_dagcbor::de::from_reader_once(&mut bytes).unwrap();
+        let body = serde_ipld_dagcbor::from_slice(bytes).unwrap();
+        (header, body)
+    }
+
+    fn t(header: &ipld_core::ipld::Ipld) -> Option<&str> {
+        match header.get("t").ok()?? {
+            ipld_core::ipld::Ipld::String(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    async fn serve_locally(db_pool: &sqlx::PgPool) -> std::net::SocketAddr {
+        let (tx, rx) = tokio::sync::watch::channel(());
+        tokio::spawn({
+            let db_pool = db_pool.clone();
+            async move { watch(&db_pool, tx).await }
+        });
+        let app = super::super::router(super::super::State {
+            db_pool: db_pool.clone(),
+            did: atrium_api::types::string::Did::new("did:plc:labeler".to_string()).unwrap(),
+            new_labels: rx,
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    async fn next_frame<B: serde::de::DeserializeOwned>(
+        ws: &mut tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    ) -> (ipld_core::ipld::Ipld, B) {
+        loop {
+            let message = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+                .await
+                .expect("no frame within 5s")
+                .unwrap()
+                .unwrap();
+            if let Message::Binary(bytes) = message {
+                return unframe(&bytes);
+            }
+        }
+    }
+
+    // Against a local Postgres: run with `DATABASE_URL=… cargo test -- --ignored`.
+    // A subscriber replays from its cursor, then sees a label committed
+    // after it connected, in seq order with signed payloads intact.
+    #[tokio::test]
+    #[ignore]
+    async fn replays_from_cursor_then_streams_live() {
+        let db_pool = crate::test_db::pool().await;
+        let keypair = crate::test_db::keypair();
+        let did = "did:plc:labeler";
+
+        let mut seqs = vec![];
+        for (uri, val, neg) in [
+            ("did:plc:a", "examplecon-mmxxv", false),
+            ("did:plc:b", "examplecon-mmxxv", false),
+            ("did:plc:a", "examplecon-mmxxv", true),
+        ] {
+            let mut tx = db_pool.begin().await.unwrap();
+            seqs.push(
+                crate::labels::emit(&keypair, &mut tx, &label(did, uri, val, neg), "3kabc")
+                    .await
+                    .unwrap()
+                    .unwrap(),
+            );
+            tx.commit().await.unwrap();
+        }
+
+        let addr = serve_locally(&db_pool).await;
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/xrpc/com.atproto.label.subscribeLabels?cursor={}",
+            seqs[0]
+        ))
+        .await
+        .unwrap();
+
+        for seq in &seqs[1..] {
+            let (header, body): (
+                _,
+                atrium_api::com::atproto::label::subscribe_labels::LabelsData,
+            ) = next_frame(&mut ws).await;
+            assert_eq!(t(&header), Some("#labels"));
+            assert_eq!(body.seq, *seq);
+            assert_eq!(body.labels.len(), 1);
+            assert!(body.labels[0].sig.is_some());
+        }
+
+        let mut tx = db_pool.begin().await.unwrap();
+        let live = crate::labels::emit(
+            &keypair,
+            &mut tx,
+            &label(did, "did:plc:c", "examplecon-mmxxv", false),
+            "3kdef",
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        tx.commit().await.unwrap();
+
+        let (header, body): (
+            _,
+            atrium_api::com::atproto::label::subscribe_labels::LabelsData,
+        ) = next_frame(&mut ws).await;
+        assert_eq!(t(&header), Some("#labels"));
+        assert_eq!(body.seq, live);
+        assert_eq!(body.labels[0].uri, "did:plc:c");
+    }
+
+    // A cursor past the head is an error frame, then a close.
+    #[tokio::test]
+    #[ignore]
+    async fn future_cursor_is_an_error_frame() {
+        let db_pool = crate::test_db::pool().await;
+        let addr = serve_locally(&db_pool).await;
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/xrpc/com.atproto.label.subscribeLabels?cursor=1000"
+        ))
+        .await
+        .unwrap();
+        let (header, body): (_, ipld_core::ipld::Ipld) = next_frame(&mut ws).await;
+        assert_eq!(
+            header.get("op").unwrap(),
+            Some(&ipld_core::ipld::Ipld::Integer(-1))
+        );
+        assert_eq!(
+            body.get("error").unwrap(),
+            Some(&ipld_core::ipld::Ipld::String("FutureCursor".to_string()))
+        );
+    }
+
+    // After compaction emptied the log, its horizon is still the head, and a
+    // cursor below it is told its labels may be gone.
+    #[tokio::test]
+    #[ignore]
+    async fn compacted_cursor_is_outdated() {
+        let db_pool = crate::test_db::pool().await;
+        sqlx::query("INSERT INTO labels_compaction (horizon) VALUES (1000)")
+            .execute(&db_pool)
+            .await
+            .unwrap();
+        let addr = serve_locally(&db_pool).await;
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/xrpc/com.atproto.label.subscribeLabels?cursor=5"
+        ))
+        .await
+        .unwrap();
+        let (header, body): (
+            _,
+            atrium_api::com::atproto::label::subscribe_labels::InfoData,
+        ) = next_frame(&mut ws).await;
+        assert_eq!(t(&header), Some("#info"));
+        assert_eq!(body.name, "OutdatedCursor");
+
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/xrpc/com.atproto.label.subscribeLabels?cursor=1000"
+        ))
+        .await
+        .unwrap();
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(500), ws.next())
+                .await
+                .is_err(),
+            "a cursor at the horizon is neither outdated nor in the future"
+        );
+    }
+}